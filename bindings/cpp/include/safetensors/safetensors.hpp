@@ -11,6 +11,13 @@
 #include <utility>
 #include <vector>
 #include <algorithm>
+#include <atomic>
+#include <chrono>
+#include <concepts>
+#include <exception>
+#include <stdexcept>
+#include <string_view>
+#include <thread>
 #include <unordered_map>
 
 #include "fmt/format.h"
@@ -22,6 +29,83 @@ namespace safetensors {
 
 constexpr std::size_t N_LEN = 8;
 
+// Call `callback(std::string_view name, const TensorView& view)` once per
+// tensor, in payload order, without building the full tensor list. Views
+// borrow from `buffer` and are only valid while it is alive.
+template <typename F>
+void deserialize_each(rust::Slice<std::uint8_t const> buffer, F&& callback) {
+  auto stream = deserialize_stream(buffer);
+  while (!stream->done()) {
+    auto item = stream->next();
+    callback(std::string_view(item.key.data(), item.key.size()),
+             static_cast<const TensorView&>(item.value));
+  }
+}
+
+// Load a file from a custom source, e.g. a decryption stream, through
+// `read_at(std::size_t offset, rust::Slice<std::uint8_t> buf) -> std::size_t`
+// which fills `buf` with the bytes at `offset` and returns how many it wrote.
+// Bytes are asked for in order, at most 1MB at a time.
+template <typename F>
+rust::Vec<PairStrOwnedTensorView> deserialize_from_reader(F&& read_at) {
+  constexpr std::size_t kChunk = std::size_t{1} << 20;
+  auto loader = new_reader_loader();
+  std::vector<std::uint8_t> chunk;
+  while (std::size_t remaining = loader->remaining()) {
+    chunk.resize(std::min(remaining, kChunk));
+    std::size_t n = read_at(loader->offset(),
+                            rust::Slice<std::uint8_t>(chunk.data(), chunk.size()));
+    if (n == 0 || n > chunk.size()) {
+      throw std::runtime_error(
+          fmt::format("{}:{} reader returned {} bytes at offset {}", __FILE__,
+                      __LINE__, n, loader->offset()));
+    }
+    loader->feed(rust::Slice<std::uint8_t const>(chunk.data(), n));
+  }
+  return loader->finish();
+}
+
+// Same as `serialize_to_file`, calling `on_progress(std::size_t written,
+// std::size_t total)` on the calling thread as bytes reach the file, and a
+// last time once the file is complete. cxx can't hand C++ callables to
+// Rust, so the export runs on its own thread and its `SerializeProgress` is
+// polled every `interval`; errors are rethrown here.
+template <typename F>
+  requires std::invocable<F&, std::size_t, std::size_t>
+void serialize_to_file_progress(
+    rust::Vec<PairStrTensorView> data, rust::Vec<PairStrStr> data_info,
+    rust::Str path, F&& on_progress,
+    std::chrono::milliseconds interval = std::chrono::milliseconds(50)) {
+  auto progress = new_serialize_progress();
+  std::atomic<bool> done{false};
+  std::exception_ptr error;
+  {
+    // Joined on scope exit, even if `on_progress` throws.
+    std::jthread exporter([&] {
+      try {
+        serialize_to_file_progress(std::move(data), std::move(data_info), path,
+                                   *progress);
+      } catch (...) {
+        error = std::current_exception();
+      }
+      done.store(true, std::memory_order_release);
+    });
+    std::size_t reported = 0;
+    while (!done.load(std::memory_order_acquire)) {
+      std::size_t written = progress->written();
+      if (written != reported) {
+        reported = written;
+        on_progress(written, progress->total());
+      }
+      std::this_thread::sleep_for(interval);
+    }
+  }
+  if (error) {
+    std::rethrow_exception(error);
+  }
+  on_progress(progress->written(), progress->total());
+}
+
 class SafeOpen {
  public:
   struct TensorView {
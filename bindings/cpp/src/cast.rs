@@ -0,0 +1,256 @@
+use crate::error::CxxError;
+use half::{bf16, f16};
+use safetensors::Dtype as RDtype;
+
+/// Floating point dtypes that can be decoded and re-encoded element-wise.
+pub(crate) fn is_float(dtype: RDtype) -> bool {
+    matches!(
+        dtype,
+        RDtype::F16 | RDtype::BF16 | RDtype::F32 | RDtype::F64
+    )
+}
+
+/// Decode a little-endian floating point payload into `f64` values.
+pub(crate) fn decode_f64(dtype: RDtype, data: &[u8]) -> Result<Vec<f64>, CxxError> {
+    let values = match dtype {
+        RDtype::F16 => data
+            .chunks_exact(2)
+            .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f64())
+            .collect(),
+        RDtype::BF16 => data
+            .chunks_exact(2)
+            .map(|b| bf16::from_le_bytes([b[0], b[1]]).to_f64())
+            .collect(),
+        RDtype::F32 => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        RDtype::F64 => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+        _ => return Err(CxxError::UnsupportedDtype(dtype)),
+    };
+    Ok(values)
+}
+
+/// Decode one FP8 value. E4M3 is the `fn` variant of the OCP spec: no
+/// infinities, a single NaN mantissa and a max of 448. E5M2 follows IEEE 754,
+/// with infinities and NaNs on the all-ones exponent.
+pub(crate) fn fp8_to_f32(dtype: RDtype, byte: u8) -> Result<f32, CxxError> {
+    let sign = if byte & 0x80 != 0 { -1.0 } else { 1.0 };
+    let (exponent, mantissa, mantissa_bits, bias) = match dtype {
+        RDtype::F8_E4M3 => ((byte >> 3) & 0x0f, byte & 0x07, 3, 7),
+        RDtype::F8_E5M2 => ((byte >> 2) & 0x1f, byte & 0x03, 2, 15),
+        _ => return Err(CxxError::UnsupportedDtype(dtype)),
+    };
+    let max_exponent = (1 << (7 - mantissa_bits)) - 1;
+    match dtype {
+        RDtype::F8_E4M3 if exponent == max_exponent && mantissa == 0x07 => return Ok(f32::NAN),
+        RDtype::F8_E5M2 if exponent == max_exponent => {
+            return Ok(if mantissa == 0 {
+                sign * f32::INFINITY
+            } else {
+                f32::NAN
+            });
+        }
+        _ => {}
+    }
+
+    let scale = (1 << mantissa_bits) as f32;
+    let value = if exponent == 0 {
+        // Subnormal: no implicit leading one, exponent pinned to 1 - bias.
+        mantissa as f32 / scale * 2f32.powi(1 - bias)
+    } else {
+        (1.0 + mantissa as f32 / scale) * 2f32.powi(exponent as i32 - bias)
+    };
+    Ok(sign * value)
+}
+
+/// Decode one F8_E8M0 value, an exponent-only scale: `2^(byte - 127)`, from
+/// 2^-127 to 2^127, `0xff` being NaN. There is no sign nor zero.
+pub(crate) fn e8m0_to_f32(byte: u8) -> f32 {
+    match byte {
+        0xff => f32::NAN,
+        // 2^-127 is below the smallest normal f32, hence a subnormal.
+        0 => f32::from_bits(1 << 22),
+        _ => f32::from_bits((byte as u32) << 23),
+    }
+}
+
+/// Decode any byte-aligned numeric payload into `f64` values, booleans
+/// becoming `0.0`/`1.0` and FP8 decoded per `fp8_to_f32`. 64-bit integers beyond 2^53 lose precision.
+pub(crate) fn to_f64(dtype: RDtype, data: &[u8]) -> Result<Vec<f64>, CxxError> {
+    let values = match dtype {
+        RDtype::BOOL => data
+            .iter()
+            .map(|&b| if b != 0 { 1.0 } else { 0.0 })
+            .collect(),
+        RDtype::U8 => data.iter().map(|&b| b as f64).collect(),
+        RDtype::I8 => data.iter().map(|&b| b as i8 as f64).collect(),
+        RDtype::F8_E4M3 | RDtype::F8_E5M2 => data
+            .iter()
+            .map(|&b| fp8_to_f32(dtype, b).map(f64::from))
+            .collect::<Result<_, _>>()?,
+        RDtype::U16 => data
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        RDtype::I16 => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        RDtype::U32 => data
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        RDtype::I32 => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        RDtype::U64 => data
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as f64)
+            .collect(),
+        RDtype::I64 => data
+            .chunks_exact(8)
+            .map(|b| i64::from_le_bytes(b.try_into().unwrap()) as f64)
+            .collect(),
+        _ => return decode_f64(dtype, data),
+    };
+    Ok(values)
+}
+
+/// Decode an integer payload into `i64` values. Fails on U64 values above
+/// `i64::MAX` and on non-integer dtypes.
+pub(crate) fn decode_i64(dtype: RDtype, data: &[u8]) -> Result<Vec<i64>, CxxError> {
+    let values = match dtype {
+        RDtype::U8 => data.iter().map(|&b| b as i64).collect(),
+        RDtype::I8 => data.iter().map(|&b| b as i8 as i64).collect(),
+        RDtype::U16 => data
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as i64)
+            .collect(),
+        RDtype::I16 => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as i64)
+            .collect(),
+        RDtype::U32 => data
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as i64)
+            .collect(),
+        RDtype::I32 => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as i64)
+            .collect(),
+        RDtype::I64 => data
+            .chunks_exact(8)
+            .map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+        RDtype::U64 => data
+            .chunks_exact(8)
+            .map(|b| {
+                let value = u64::from_le_bytes(b.try_into().unwrap());
+                i64::try_from(value).map_err(|_| CxxError::OutOfRange(value.to_string()))
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(CxxError::UnsupportedDtype(dtype)),
+    };
+    Ok(values)
+}
+
+/// Encode `f64` values as a little-endian payload of the given floating point dtype.
+/// Narrowing rounds to nearest, ties to even.
+pub(crate) fn encode_f64(dtype: RDtype, values: &[f64]) -> Result<Vec<u8>, CxxError> {
+    let mut out = Vec::with_capacity(values.len() * dtype.bitsize() / 8);
+    match dtype {
+        RDtype::F16 => {
+            for &v in values {
+                out.extend(f16::from_f64(v).to_le_bytes());
+            }
+        }
+        RDtype::BF16 => {
+            for &v in values {
+                out.extend(bf16::from_f64(v).to_le_bytes());
+            }
+        }
+        RDtype::F32 => {
+            for &v in values {
+                out.extend((v as f32).to_le_bytes());
+            }
+        }
+        RDtype::F64 => {
+            for &v in values {
+                out.extend(v.to_le_bytes());
+            }
+        }
+        _ => return Err(CxxError::UnsupportedDtype(dtype)),
+    }
+    Ok(out)
+}
+
+/// Convert a floating point payload from one float dtype to another.
+pub(crate) fn cast_float(from: RDtype, to: RDtype, data: &[u8]) -> Result<Vec<u8>, CxxError> {
+    encode_f64(to, &decode_f64(from, data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cast_roundtrip() {
+        let values: Vec<u8> = [1.0f32, -2.5, 0.15625]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        for dtype in [RDtype::F16, RDtype::BF16, RDtype::F64] {
+            let casted = cast_float(RDtype::F32, dtype, &values).unwrap();
+            assert_eq!(casted.len(), 3 * dtype.bitsize() / 8);
+            let back = cast_float(dtype, RDtype::F32, &casted).unwrap();
+            assert_eq!(back, values);
+        }
+    }
+
+    #[test]
+    fn fp8_decode() {
+        let e4m3 = |b| fp8_to_f32(RDtype::F8_E4M3, b).unwrap();
+        assert_eq!(e4m3(0x00), 0.0);
+        assert_eq!(e4m3(0x38), 1.0);
+        assert_eq!(e4m3(0xc0), -2.0);
+        assert_eq!(e4m3(0x7e), 448.0);
+        assert_eq!(e4m3(0x01), 2f32.powi(-9));
+        assert_eq!(e4m3(0x78), 256.0);
+        assert!(e4m3(0x7f).is_nan());
+        assert!(e4m3(0xff).is_nan());
+
+        let e5m2 = |b| fp8_to_f32(RDtype::F8_E5M2, b).unwrap();
+        assert_eq!(e5m2(0x3c), 1.0);
+        assert_eq!(e5m2(0x7b), 57344.0);
+        assert_eq!(e5m2(0x01), 2f32.powi(-16));
+        assert_eq!(e5m2(0x7c), f32::INFINITY);
+        assert_eq!(e5m2(0xfc), f32::NEG_INFINITY);
+        assert!(e5m2(0x7d).is_nan());
+
+        assert!(fp8_to_f32(RDtype::F8_E8M0, 0).is_err());
+    }
+
+    #[test]
+    fn e8m0_decode() {
+        assert_eq!(e8m0_to_f32(127), 1.0);
+        assert_eq!(e8m0_to_f32(130), 8.0);
+        assert_eq!(e8m0_to_f32(126), 0.5);
+        assert_eq!(e8m0_to_f32(254), 2f32.powi(127));
+        assert_eq!(e8m0_to_f32(1), 2f32.powi(-126));
+        assert_eq!(e8m0_to_f32(0), 2f64.powi(-127) as f32);
+        assert!(e8m0_to_f32(0xff).is_nan());
+    }
+
+    #[test]
+    fn cast_rejects_non_float() {
+        assert!(matches!(
+            cast_float(RDtype::I32, RDtype::F16, &[0; 4]),
+            Err(CxxError::UnsupportedDtype(RDtype::I32))
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+//! Tensors gathered one at a time before being serialized together.
+use crate::error::CxxError;
+use crate::ffi::{PairStrStr, TensorView};
+use crate::{convert_to_hashmap_string, prepare_tensor, CowTensor};
+use safetensors::View;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Builder returned by `new_collection`. Tensors are validated and copied
+/// when inserted, so the caller's buffers can be released right away.
+#[derive(Default)]
+pub struct TensorCollection {
+    tensors: HashMap<String, CowTensor<'static>>,
+}
+
+/// Empty collection.
+pub fn new_collection() -> Box<TensorCollection> {
+    Box::default()
+}
+
+impl TensorCollection {
+    /// Add `view` under `name`, with the same checks as `serialize`. Fails
+    /// if the name is already taken.
+    pub fn insert(&mut self, name: &str, view: TensorView) -> Result<(), CxxError> {
+        if self.tensors.contains_key(name) {
+            return Err(CxxError::DuplicateName(name.to_string()));
+        }
+        let view = prepare_tensor(name, view)?;
+        let tensor = CowTensor {
+            dtype: view.dtype(),
+            shape: view.shape,
+            data: Cow::Owned(view.data.to_vec()),
+        };
+        self.tensors.insert(name.to_string(), tensor);
+        Ok(())
+    }
+
+    /// Serialize every tensor inserted so far, in the same layout as
+    /// `serialize`. The collection is left untouched.
+    pub fn serialize_collection(&self, data_info: Vec<PairStrStr>) -> Result<Vec<u8>, CxxError> {
+        let tensors = self.tensors.iter().map(|(name, tensor)| {
+            let tensor = CowTensor {
+                dtype: tensor.dtype,
+                shape: tensor.shape.clone(),
+                data: Cow::Borrowed(tensor.data.as_ref()),
+            };
+            (name, tensor)
+        });
+        Ok(safetensors::serialize(
+            tensors,
+            convert_to_hashmap_string(data_info),
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::Dtype;
+    use safetensors::SafeTensors;
+
+    fn view(dtype: Dtype, shape: Vec<usize>, data: &[u8]) -> TensorView<'_> {
+        TensorView {
+            shape,
+            dtype,
+            data,
+            data_len: data.len(),
+        }
+    }
+
+    #[test]
+    fn incremental_build() {
+        let mut collection = new_collection();
+        {
+            // Dropped before serializing, the collection holds its own copy.
+            let weight = vec![1u8; 8];
+            collection
+                .insert("weight", view(Dtype::F32, vec![2], &weight))
+                .unwrap();
+        }
+        collection
+            .insert("bias", view(Dtype::U8, vec![3], &[1, 2, 3]))
+            .unwrap();
+
+        assert!(matches!(
+            collection.insert("bias", view(Dtype::U8, vec![3], &[1, 2, 3])),
+            Err(CxxError::DuplicateName(name)) if name == "bias"
+        ));
+        assert!(matches!(
+            collection.insert("short", view(Dtype::F32, vec![2], &[0; 7])),
+            Err(CxxError::InvalidTensor(name, _)) if name == "short"
+        ));
+        assert!(matches!(
+            collection.insert("__metadata__", view(Dtype::U8, vec![1], &[0])),
+            Err(CxxError::ReservedName(_))
+        ));
+
+        let data_info = vec![PairStrStr {
+            key: "format".to_string(),
+            value: "pt".to_string(),
+        }];
+        let out = collection.serialize_collection(data_info).unwrap();
+        let loaded = SafeTensors::deserialize(&out).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.tensor("weight").unwrap().data(), &[1; 8]);
+        assert_eq!(loaded.tensor("bias").unwrap().data(), &[1, 2, 3]);
+    }
+}
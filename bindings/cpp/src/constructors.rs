@@ -0,0 +1,294 @@
+//! Typed constructors building a tensor from native C++ values, so callers
+//! don't have to lay out the bytes of each dtype by hand.
+use crate::error::CxxError;
+use crate::ffi::{ByteChunk, Dtype, OwnedTensorView, TensorView};
+use crate::{check_view, tensor_view_checked, view_size};
+use cxx::CxxVector;
+use safetensors::SafeTensorError;
+
+/// BOOL tensor with one byte per value, `0` or `1`, as the format requires.
+/// `shape` must hold exactly `data.len()` elements.
+pub fn tensor_from_bool(shape: Vec<usize>, data: &[bool]) -> Result<OwnedTensorView, CxxError> {
+    let bytes = data.iter().map(|&b| b as u8).collect();
+    tensor_view_checked(Dtype::BOOL, shape, bytes)
+}
+
+/// Tensor made of `chunks` joined end to end, e.g. from scatter/gather
+/// buffers, copied into a single buffer. Their total length must be exactly
+/// what `dtype` and `shape` call for.
+pub fn tensor_from_chunks(
+    dtype: Dtype,
+    shape: Vec<usize>,
+    chunks: Vec<ByteChunk>,
+) -> Result<OwnedTensorView, CxxError> {
+    let len = chunks
+        .iter()
+        .try_fold(0usize, |len, chunk| len.checked_add(chunk.data.len()))
+        .ok_or(SafeTensorError::ValidationOverflow)?;
+    let mut data = Vec::with_capacity(len);
+    for chunk in chunks {
+        data.extend_from_slice(chunk.data);
+    }
+    tensor_view_checked(dtype, shape, data)
+}
+
+/// Tensor with every element set to `value_bytes`, one little-endian value
+/// of `dtype`, e.g. a zero bias or a ones scale filling in a missing tensor.
+/// Sub-byte dtypes, whose values don't take whole bytes, are rejected.
+pub fn tensor_filled(
+    dtype: Dtype,
+    shape: Vec<usize>,
+    value_bytes: &[u8],
+) -> Result<OwnedTensorView, CxxError> {
+    let rdtype = safetensors::Dtype::try_from(dtype)?;
+    if rdtype.bitsize() % 8 != 0 {
+        return Err(CxxError::UnsupportedDtype(rdtype));
+    }
+    if value_bytes.len() != rdtype.bitsize() / 8 {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+    let n = view_size(rdtype, &shape)? / value_bytes.len();
+    tensor_view_checked(dtype, shape, value_bytes.repeat(n))
+}
+
+/// F32 tensor borrowing the storage of a `std::vector<float>`, no copy
+/// being made: the vector must outlive the view and not be resized while
+/// it's in use. `shape` must hold exactly `data.size()` elements. Fails on
+/// big-endian targets, whose native floats aren't the little-endian bytes
+/// the format stores.
+pub fn tensor_from_cxx_f32<'a>(
+    shape: Vec<usize>,
+    data: &'a CxxVector<f32>,
+) -> Result<TensorView<'a>, CxxError> {
+    borrow_f32(shape, data.as_slice())
+}
+
+/// `tensor_from_cxx_f32` over the vector's contiguous storage.
+fn borrow_f32(shape: Vec<usize>, data: &[f32]) -> Result<TensorView<'_>, CxxError> {
+    if cfg!(target_endian = "big") {
+        return Err(CxxError::UnsupportedDtype(safetensors::Dtype::F32));
+    }
+    let data: &[u8] = bytemuck::cast_slice(data);
+    check_view(safetensors::Dtype::F32, &shape, data.len())?;
+    Ok(TensorView {
+        shape,
+        dtype: Dtype::F32,
+        data,
+        data_len: data.len(),
+    })
+}
+
+macro_rules! tensor_from {
+    ($($name:ident: $ty:ty => $dtype:ident),* $(,)?) => {$(
+        #[doc = concat!("`", stringify!($dtype), "` tensor holding `data`, written little-endian.")]
+        /// `shape` must hold exactly `data.len()` elements.
+        pub fn $name(shape: Vec<usize>, data: &[$ty]) -> Result<OwnedTensorView, CxxError> {
+            let bytes = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+            tensor_view_checked(Dtype::$dtype, shape, bytes)
+        }
+    )*};
+}
+
+tensor_from! {
+    tensor_from_f64: f64 => F64,
+    tensor_from_f32: f32 => F32,
+    tensor_from_i64: i64 => I64,
+    tensor_from_u64: u64 => U64,
+    tensor_from_i32: i32 => I32,
+    tensor_from_u32: u32 => U32,
+    tensor_from_i16: i16 => I16,
+    tensor_from_u16: u16 => U16,
+    tensor_from_i8: i8 => I8,
+    tensor_from_u8: u8 => U8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::PairStrTensorView;
+
+    fn roundtrip(tensor: &OwnedTensorView) -> (Vec<usize>, Dtype, Vec<u8>) {
+        let data = vec![PairStrTensorView {
+            key: "t".to_string(),
+            value: TensorView {
+                shape: tensor.shape.clone(),
+                dtype: tensor.dtype,
+                data: &tensor.data,
+                data_len: tensor.data.len(),
+            },
+        }];
+        let out = crate::serialize(data, Vec::new()).unwrap();
+        let loaded = crate::deserialize(&out).unwrap();
+        let view = &loaded[0].value;
+        (view.shape.clone(), view.dtype, view.data.to_vec())
+    }
+
+    #[test]
+    fn typed_roundtrip() {
+        let check = |tensor: OwnedTensorView, dtype: Dtype, bytes: Vec<u8>| {
+            assert_eq!(tensor.dtype, dtype);
+            assert_eq!(tensor.data, bytes);
+            assert_eq!(roundtrip(&tensor), (vec![2], dtype, bytes));
+        };
+        let le = |values: &[&[u8]]| values.concat();
+
+        check(
+            tensor_from_f64(vec![2], &[1.5, -2.0]).unwrap(),
+            Dtype::F64,
+            le(&[&1.5f64.to_le_bytes(), &(-2f64).to_le_bytes()]),
+        );
+        check(
+            tensor_from_f32(vec![2], &[1.5, -2.0]).unwrap(),
+            Dtype::F32,
+            le(&[&1.5f32.to_le_bytes(), &(-2f32).to_le_bytes()]),
+        );
+        check(
+            tensor_from_i64(vec![2], &[-1, 1 << 40]).unwrap(),
+            Dtype::I64,
+            le(&[&(-1i64).to_le_bytes(), &(1i64 << 40).to_le_bytes()]),
+        );
+        check(
+            tensor_from_u64(vec![2], &[u64::MAX, 7]).unwrap(),
+            Dtype::U64,
+            le(&[&u64::MAX.to_le_bytes(), &7u64.to_le_bytes()]),
+        );
+        check(
+            tensor_from_i32(vec![2], &[-3, 70000]).unwrap(),
+            Dtype::I32,
+            vec![0xfd, 0xff, 0xff, 0xff, 0x70, 0x11, 0x01, 0x00],
+        );
+        check(
+            tensor_from_u32(vec![2], &[1, 0x01020304]).unwrap(),
+            Dtype::U32,
+            vec![1, 0, 0, 0, 4, 3, 2, 1],
+        );
+        check(
+            tensor_from_i16(vec![2], &[-2, 258]).unwrap(),
+            Dtype::I16,
+            vec![0xfe, 0xff, 2, 1],
+        );
+        check(
+            tensor_from_u16(vec![2], &[0xbeef, 1]).unwrap(),
+            Dtype::U16,
+            vec![0xef, 0xbe, 1, 0],
+        );
+        check(
+            tensor_from_i8(vec![2], &[-1, 5]).unwrap(),
+            Dtype::I8,
+            vec![0xff, 5],
+        );
+        check(
+            tensor_from_u8(vec![2], &[200, 5]).unwrap(),
+            Dtype::U8,
+            vec![200, 5],
+        );
+
+        assert!(matches!(
+            tensor_from_i32(vec![3], &[1, 2]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn borrowed_f32() {
+        let values = [1.5f32, -2.0, 0.25, 8.0];
+        let view = borrow_f32(vec![2, 2], &values).unwrap();
+        assert_eq!(view.data.as_ptr(), values.as_ptr().cast());
+        let owned = tensor_from_f32(vec![2, 2], &values).unwrap();
+        assert_eq!(view.data, owned.data.as_slice());
+        assert!(matches!(
+            borrow_f32(vec![3], &values),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn chunked() {
+        let (head, tail) = ([1u8, 0, 2], [0u8, 3, 0]);
+        let chunks = || {
+            vec![
+                ByteChunk { data: &head },
+                ByteChunk { data: &[] },
+                ByteChunk { data: &tail },
+            ]
+        };
+        let tensor = tensor_from_chunks(Dtype::U16, vec![3], chunks()).unwrap();
+        assert_eq!(tensor.data, vec![1, 0, 2, 0, 3, 0]);
+        assert_eq!(
+            roundtrip(&tensor),
+            (vec![3], Dtype::U16, tensor.data.clone())
+        );
+
+        assert!(matches!(
+            tensor_from_chunks(Dtype::U16, vec![2], chunks()),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(..)
+            ))
+        ));
+    }
+
+    #[test]
+    fn filled() {
+        let one = 1.0f32.to_le_bytes();
+        let tensor = tensor_filled(Dtype::F32, vec![2, 3], &one).unwrap();
+        assert_eq!(tensor.data, one.repeat(6));
+        assert_eq!(roundtrip(&tensor).0, vec![2, 3]);
+
+        let scalar = tensor_filled(Dtype::I8, vec![], &[0xff]).unwrap();
+        assert_eq!(scalar.data, vec![0xff]);
+        assert!(tensor_filled(Dtype::U16, vec![0], &[0, 0])
+            .unwrap()
+            .data
+            .is_empty());
+
+        assert!(matches!(
+            tensor_filled(Dtype::F32, vec![2], &[0; 2]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::TensorInvalidInfo
+            ))
+        ));
+        assert!(matches!(
+            tensor_filled(Dtype::F4, vec![2], &[0]),
+            Err(CxxError::UnsupportedDtype(_))
+        ));
+        assert!(tensor_filled(Dtype::U8, vec![usize::MAX, 2], &[0]).is_err());
+    }
+
+    #[test]
+    fn bool_roundtrip() {
+        let values = [true, false, false, true, true, false];
+        let tensor = tensor_from_bool(vec![2, 3], &values).unwrap();
+        assert_eq!(tensor.dtype, Dtype::BOOL);
+        assert_eq!(tensor.data, vec![1, 0, 0, 1, 1, 0]);
+
+        let (shape, _, data) = roundtrip(&tensor);
+        assert_eq!(shape, vec![2, 3]);
+        let decoded: Vec<bool> = data.iter().map(|&b| b != 0).collect();
+        assert_eq!(decoded, values);
+
+        // One byte per element, so any other length is rejected on serialize.
+        let data = vec![PairStrTensorView {
+            key: "mask".to_string(),
+            value: TensorView {
+                shape: vec![4],
+                dtype: Dtype::BOOL,
+                data: &tensor.data,
+                data_len: tensor.data.len(),
+            },
+        }];
+        assert!(matches!(
+            crate::serialize(data, Vec::new()),
+            Err(CxxError::InvalidTensor(name, _)) if name == "mask"
+        ));
+        assert!(matches!(
+            tensor_from_bool(vec![4], &values),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(..)
+            ))
+        ));
+    }
+}
@@ -1,11 +1,139 @@
 use safetensors::Dtype as RDtype;
 
-use crate::ffi::Dtype;
+use crate::error::CxxError;
+use crate::ffi::{Dtype, DtypeGroup};
 
-// Upload: Rust -> Cxx
-impl Into<Dtype> for RDtype {
-    fn into(self) -> Dtype {
-        match self {
+/// Every dtype the bindings convert to and from `safetensors`, in the same
+/// increasing alignment order as the enum.
+const SUPPORTED_DTYPES: [Dtype; 19] = [
+    Dtype::BOOL,
+    Dtype::F4,
+    Dtype::F6_E2M3,
+    Dtype::F6_E3M2,
+    Dtype::U8,
+    Dtype::I8,
+    Dtype::F8_E5M2,
+    Dtype::F8_E4M3,
+    Dtype::F8_E8M0,
+    Dtype::I16,
+    Dtype::U16,
+    Dtype::F16,
+    Dtype::BF16,
+    Dtype::I32,
+    Dtype::U32,
+    Dtype::F32,
+    Dtype::F64,
+    Dtype::I64,
+    Dtype::U64,
+];
+
+/// Version of the `safetensors` crate the bindings were built against.
+pub fn safetensors_version() -> String {
+    safetensors::VERSION.to_string()
+}
+
+/// Dtypes this build can read and write.
+pub fn supported_dtypes() -> Vec<Dtype> {
+    SUPPORTED_DTYPES.to_vec()
+}
+
+/// Width of one element of `dtype` in bits, e.g. 4 for F4 and 6 for the F6
+/// variants, which a size in bytes can't express.
+pub fn dtype_bits(dtype: Dtype) -> Result<usize, CxxError> {
+    Ok(RDtype::try_from(dtype)?.bitsize())
+}
+
+/// Family `dtype` belongs to, see `DtypeGroup`.
+pub fn dtype_group(dtype: Dtype) -> Result<DtypeGroup, CxxError> {
+    Ok(match RDtype::try_from(dtype)? {
+        RDtype::F16 | RDtype::BF16 | RDtype::F32 | RDtype::F64 => DtypeGroup::Float,
+        RDtype::I8 | RDtype::I16 | RDtype::I32 | RDtype::I64 => DtypeGroup::SignedInt,
+        RDtype::U8 | RDtype::U16 | RDtype::U32 | RDtype::U64 => DtypeGroup::UnsignedInt,
+        RDtype::BOOL => DtypeGroup::Bool,
+        RDtype::F4 | RDtype::F6_E2M3 | RDtype::F6_E3M2 => DtypeGroup::SubByteFloat,
+        RDtype::F8_E5M2 | RDtype::F8_E4M3 => DtypeGroup::Fp8,
+        RDtype::F8_E8M0 => DtypeGroup::ScaleExponent,
+        other => return Err(CxxError::UnsupportedDtype(other)),
+    })
+}
+
+/// Dtype of the result of combining `a` and `b`, e.g. for a concatenation,
+/// following NumPy except that integers mixed with floats promote to the
+/// float, as in PyTorch:
+/// - booleans promote to the other dtype;
+/// - floats, and integers of the same signedness, to the wider one, F16 and
+///   BF16 to F32;
+/// - signed and unsigned integers to the smallest signed integer holding
+///   both, F64 for U64;
+/// - integers and floats to the float.
+///
+/// Sub-byte, FP8 and F8_E8M0 dtypes only combine with themselves, except
+/// F4, F6 and FP8 values which widen to F16, BF16, F32 or F64 losslessly.
+pub fn promote_dtype(a: Dtype, b: Dtype) -> Result<Dtype, CxxError> {
+    #[derive(Clone, Copy)]
+    enum Kind {
+        Bool,
+        Unsigned(usize),
+        Signed(usize),
+        Float,
+        Exotic,
+    }
+
+    fn kind(dtype: RDtype) -> Kind {
+        match dtype {
+            RDtype::BOOL => Kind::Bool,
+            RDtype::U8 | RDtype::U16 | RDtype::U32 | RDtype::U64 => Kind::Unsigned(dtype.bitsize()),
+            RDtype::I8 | RDtype::I16 | RDtype::I32 | RDtype::I64 => Kind::Signed(dtype.bitsize()),
+            RDtype::F16 | RDtype::BF16 | RDtype::F32 | RDtype::F64 => Kind::Float,
+            _ => Kind::Exotic,
+        }
+    }
+
+    fn signed(bits: usize) -> RDtype {
+        match bits {
+            8 => RDtype::I8,
+            16 => RDtype::I16,
+            32 => RDtype::I32,
+            _ => RDtype::I64,
+        }
+    }
+
+    let (a, b): (RDtype, RDtype) = (a.try_into()?, b.try_into()?);
+    if a == b {
+        return a.try_into();
+    }
+    let promoted = match (kind(a), kind(b)) {
+        (Kind::Bool, _) => b,
+        (_, Kind::Bool) => a,
+        (Kind::Float, Kind::Float) if a.bitsize() == b.bitsize() => RDtype::F32,
+        (Kind::Float, Kind::Float)
+        | (Kind::Unsigned(_), Kind::Unsigned(_))
+        | (Kind::Signed(_), Kind::Signed(_)) => std::cmp::max_by_key(a, b, |dtype| dtype.bitsize()),
+        (Kind::Signed(s), Kind::Unsigned(u)) | (Kind::Unsigned(u), Kind::Signed(s)) => {
+            if u < s {
+                signed(s)
+            } else if u < 64 {
+                signed(u * 2)
+            } else {
+                RDtype::F64
+            }
+        }
+        (Kind::Float, Kind::Unsigned(_) | Kind::Signed(_)) => a,
+        (Kind::Unsigned(_) | Kind::Signed(_), Kind::Float) => b,
+        (Kind::Float, Kind::Exotic) if b != RDtype::F8_E8M0 => a,
+        (Kind::Exotic, Kind::Float) if a != RDtype::F8_E8M0 => b,
+        _ => return Err(CxxError::IncompatibleDtypes(a, b)),
+    };
+    promoted.try_into()
+}
+
+// Upload: Rust -> Cxx. Fails for dtypes `safetensors` knows but the bridge
+// doesn't expose yet.
+impl TryFrom<RDtype> for Dtype {
+    type Error = CxxError;
+
+    fn try_from(dtype: RDtype) -> Result<Dtype, CxxError> {
+        Ok(match dtype {
             RDtype::BOOL => Dtype::BOOL,
             RDtype::F4 => Dtype::F4,
             RDtype::F6_E2M3 => Dtype::F6_E2M3,
@@ -25,15 +153,18 @@ impl Into<Dtype> for RDtype {
             RDtype::F64 => Dtype::F64,
             RDtype::I64 => Dtype::I64,
             RDtype::U64 => Dtype::U64,
-            _ => todo!(),
-        }
+            _ => return Err(CxxError::UnsupportedDtype(dtype)),
+        })
     }
 }
 
-// Download: Cxx -> Rust
-impl Into<RDtype> for Dtype {
-    fn into(self) -> RDtype {
-        match self {
+// Download: Cxx -> Rust. Fails for values outside the enum, which C++ can
+// build with a cast.
+impl TryFrom<Dtype> for RDtype {
+    type Error = CxxError;
+
+    fn try_from(dtype: Dtype) -> Result<RDtype, CxxError> {
+        Ok(match dtype {
             Dtype::BOOL => RDtype::BOOL,
             Dtype::F4 => RDtype::F4,
             Dtype::F6_E2M3 => RDtype::F6_E2M3,
@@ -53,7 +184,149 @@ impl Into<RDtype> for Dtype {
             Dtype::F64 => RDtype::F64,
             Dtype::I64 => RDtype::I64,
             Dtype::U64 => RDtype::U64,
-            _ => todo!(),
+            _ => return Err(CxxError::UnknownDtype(format!("{dtype:?}"))),
+        })
+    }
+}
+
+/// Parse a dtype name, accepting the canonical safetensors spelling in any
+/// case (`F32`, `bf16`) as well as the usual framework aliases (`float32`,
+/// `fp16`, `half`, `int64`, `float8_e4m3fn`, ...).
+pub fn dtype_from_name_lenient(name: &str) -> Result<Dtype, CxxError> {
+    let canonical = serde_json::Value::String(name.to_ascii_uppercase());
+    if let Ok(dtype) = serde_json::from_value::<RDtype>(canonical) {
+        return dtype.try_into();
+    }
+    let dtype = match name.to_ascii_lowercase().as_str() {
+        "bool" | "boolean" => RDtype::BOOL,
+        "uint8" | "byte" => RDtype::U8,
+        "int8" | "char" => RDtype::I8,
+        "int16" | "short" => RDtype::I16,
+        "uint16" => RDtype::U16,
+        "float16" | "fp16" | "half" => RDtype::F16,
+        "bfloat16" => RDtype::BF16,
+        "int32" | "int" => RDtype::I32,
+        "uint32" => RDtype::U32,
+        "float32" | "fp32" | "float" => RDtype::F32,
+        "float64" | "fp64" | "double" => RDtype::F64,
+        "int64" | "long" => RDtype::I64,
+        "uint64" => RDtype::U64,
+        "float8_e4m3fn" | "float8_e4m3" | "fp8_e4m3" | "e4m3" => RDtype::F8_E4M3,
+        "float8_e5m2" | "fp8_e5m2" | "e5m2" => RDtype::F8_E5M2,
+        "float8_e8m0fnu" | "float8_e8m0" | "e8m0" => RDtype::F8_E8M0,
+        _ => return Err(CxxError::UnknownDtype(name.to_string())),
+    };
+    dtype.try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lenient_names() {
+        assert_eq!(dtype_from_name_lenient("F32").unwrap(), Dtype::F32);
+        assert_eq!(dtype_from_name_lenient("bf16").unwrap(), Dtype::BF16);
+        assert_eq!(dtype_from_name_lenient("f8_e4m3").unwrap(), Dtype::F8_E4M3);
+        assert_eq!(dtype_from_name_lenient("float32").unwrap(), Dtype::F32);
+        assert_eq!(dtype_from_name_lenient("fp16").unwrap(), Dtype::F16);
+        assert_eq!(dtype_from_name_lenient("Int64").unwrap(), Dtype::I64);
+        assert_eq!(dtype_from_name_lenient("int8").unwrap(), Dtype::I8);
+        assert!(matches!(
+            dtype_from_name_lenient("complex64"),
+            Err(CxxError::UnknownDtype(name)) if name == "complex64"
+        ));
+    }
+
+    #[test]
+    fn supported() {
+        let dtypes = supported_dtypes();
+        assert!(dtypes.windows(2).all(|w| w[0] < w[1]));
+        for dtype in dtypes {
+            assert_eq!(
+                Dtype::try_from(RDtype::try_from(dtype).unwrap()).unwrap(),
+                dtype
+            );
+        }
+        assert!(matches!(
+            RDtype::try_from(Dtype { repr: 200 }),
+            Err(CxxError::UnknownDtype(_))
+        ));
+        let version = safetensors_version();
+        let release = version.split(['-', '+']).next().unwrap();
+        assert_eq!(release.split('.').count(), 3, "{version}");
+        assert!(
+            release.split('.').all(|part| part.parse::<u64>().is_ok()),
+            "{version}"
+        );
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(dtype_group(Dtype::BF16).unwrap(), DtypeGroup::Float);
+        assert_eq!(dtype_group(Dtype::I8).unwrap(), DtypeGroup::SignedInt);
+        assert_eq!(dtype_group(Dtype::U64).unwrap(), DtypeGroup::UnsignedInt);
+        assert_eq!(dtype_group(Dtype::BOOL).unwrap(), DtypeGroup::Bool);
+        assert_eq!(
+            dtype_group(Dtype::F6_E3M2).unwrap(),
+            DtypeGroup::SubByteFloat
+        );
+        assert_eq!(dtype_group(Dtype::F8_E4M3).unwrap(), DtypeGroup::Fp8);
+        assert_eq!(
+            dtype_group(Dtype::F8_E8M0).unwrap(),
+            DtypeGroup::ScaleExponent
+        );
+        // Every dtype falls in a group.
+        for dtype in supported_dtypes() {
+            dtype_group(dtype).unwrap();
+        }
+    }
+
+    #[test]
+    fn promotion() {
+        let promote = |a, b| {
+            let ab = promote_dtype(a, b).unwrap();
+            assert_eq!(ab, promote_dtype(b, a).unwrap());
+            ab
+        };
+        assert_eq!(promote(Dtype::F16, Dtype::F16), Dtype::F16);
+        assert_eq!(promote(Dtype::F16, Dtype::F32), Dtype::F32);
+        assert_eq!(promote(Dtype::F16, Dtype::BF16), Dtype::F32);
+        assert_eq!(promote(Dtype::I32, Dtype::F32), Dtype::F32);
+        assert_eq!(promote(Dtype::I64, Dtype::F16), Dtype::F16);
+        assert_eq!(promote(Dtype::I8, Dtype::I16), Dtype::I16);
+        assert_eq!(promote(Dtype::U8, Dtype::U32), Dtype::U32);
+        assert_eq!(promote(Dtype::I8, Dtype::U8), Dtype::I16);
+        assert_eq!(promote(Dtype::I32, Dtype::U16), Dtype::I32);
+        assert_eq!(promote(Dtype::I16, Dtype::U32), Dtype::I64);
+        assert_eq!(promote(Dtype::I8, Dtype::U64), Dtype::F64);
+        assert_eq!(promote(Dtype::BOOL, Dtype::U8), Dtype::U8);
+        assert_eq!(promote(Dtype::F8_E4M3, Dtype::BF16), Dtype::BF16);
+        assert_eq!(promote(Dtype::F4, Dtype::F4), Dtype::F4);
+
+        for (a, b) in [
+            (Dtype::F4, Dtype::F6_E2M3),
+            (Dtype::F8_E4M3, Dtype::F8_E5M2),
+            (Dtype::F8_E8M0, Dtype::F32),
+            (Dtype::F8_E4M3, Dtype::I8),
+        ] {
+            assert!(matches!(
+                promote_dtype(a, b),
+                Err(CxxError::IncompatibleDtypes(..))
+            ));
         }
     }
+
+    #[test]
+    fn bits() {
+        assert_eq!(dtype_bits(Dtype::F4).unwrap(), 4);
+        assert_eq!(dtype_bits(Dtype::F6_E2M3).unwrap(), 6);
+        assert_eq!(dtype_bits(Dtype::F6_E3M2).unwrap(), 6);
+        assert_eq!(dtype_bits(Dtype::BOOL).unwrap(), 8);
+        assert_eq!(dtype_bits(Dtype::F8_E8M0).unwrap(), 8);
+        assert_eq!(dtype_bits(Dtype::BF16).unwrap(), 16);
+        assert_eq!(dtype_bits(Dtype::I16).unwrap(), 16);
+        assert_eq!(dtype_bits(Dtype::F32).unwrap(), 32);
+        assert_eq!(dtype_bits(Dtype::U64).unwrap(), 64);
+    }
 }
@@ -0,0 +1,73 @@
+use safetensors::Dtype as RDtype;
+use safetensors::SafeTensorError;
+use thiserror::Error;
+
+/// Errors raised by the bindings on top of the ones coming from `safetensors`.
+/// They surface on the C++ side as `rust::Error` with the message below.
+#[derive(Debug, Error)]
+pub enum CxxError {
+    #[error("{0}")]
+    SafeTensorError(#[from] SafeTensorError),
+
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("unsupported dtype {0} for this operation")]
+    UnsupportedDtype(RDtype),
+
+    #[error("no common dtype for {0} and {1}")]
+    IncompatibleDtypes(RDtype, RDtype),
+
+    #[error("unknown dtype name `{0}`")]
+    UnknownDtype(String),
+
+    #[cfg(feature = "http")]
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] Box<ureq::Error>),
+
+    #[cfg_attr(feature = "http", allow(dead_code))]
+    #[error("this build of the bindings does not include the `{0}` feature")]
+    FeatureDisabled(&'static str),
+
+    #[error("tensor name `{0}` is reserved for the header metadata")]
+    ReservedName(String),
+
+    #[error("tensor name `{0}` is used more than once")]
+    DuplicateName(String),
+
+    #[error("metadata key `{0}` is used more than once")]
+    DuplicateMetadataKey(String),
+
+    #[error("per-tensor metadata key `{0}` is not of the form `<tensor>:<field>`")]
+    InvalidTensorMetaKey(String),
+
+    #[error("metadata key `{0}` is reserved for notes on tensor `{1}`")]
+    ReservedMetadataKey(String, String),
+
+    #[error("tensor name `{0}` matches several tensors: {1}")]
+    AmbiguousName(String, String),
+
+    #[error("tensor `{0}` is missing from the requested key order")]
+    UnorderedTensor(String),
+
+    #[error("value {0} does not fit in the target dtype")]
+    OutOfRange(String),
+
+    #[error("expected only {0} tensors, found {1}")]
+    UnexpectedDtypes(RDtype, String),
+
+    #[error("SHA256 mismatch: expected {0}, got {1}")]
+    ChecksumMismatch(String, String),
+
+    #[error("{0} is {1} bytes, over the limit of {2}")]
+    LimitExceeded(&'static str, u64, usize),
+
+    #[error("no tensor left in the stream")]
+    EndOfStream,
+
+    #[error("output `{0}` is also one of the inputs")]
+    OutputIsInput(String),
+
+    #[error("tensor `{0}`: {1}")]
+    InvalidTensor(String, SafeTensorError),
+}
@@ -0,0 +1,1186 @@
+use crate::cast;
+use crate::error::CxxError;
+use crate::ffi::{
+    Dtype, FlatModel, LoadedModel, OwnedTensorView, PairStrOwnedTensorView, PairStrStr,
+    PairStrTensorView, SerializeOptions,
+};
+use crate::header::tensor_locations;
+use crate::numeric::close;
+use crate::{
+    bridge_shape, convert_to_hashmap_string, deserialize_owned, header_len, layout, metadata_pairs,
+    parse_header, prepare, CowTensor, Layout, N_LEN,
+};
+use memmap2::Mmap;
+use safetensors::tensor::Metadata;
+use safetensors::Dtype as RDtype;
+use safetensors::{SafeTensorError, SafeTensors};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Read and parse the header of `path` without reading the payload.
+/// Returns the open file along with the header length and its content.
+pub(crate) fn read_header(path: &str) -> Result<(File, usize, Metadata), CxxError> {
+    let mut file = File::open(path)?;
+    let header = read_prefix_and_header(&mut file)?;
+    let metadata = parse_header(&header[N_LEN..])?;
+    Ok((file, header.len() - N_LEN, metadata))
+}
+
+/// Length prefix and header bytes at the start of `file`, unparsed.
+fn read_prefix_and_header(file: &mut File) -> Result<Vec<u8>, CxxError> {
+    let mut header_size_bytes = [0u8; N_LEN];
+    file.read_exact(&mut header_size_bytes)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => SafeTensorError::HeaderTooSmall.into(),
+            _ => CxxError::from(err),
+        })?;
+    let n = header_len(header_size_bytes)?;
+
+    let mut header = vec![0u8; N_LEN + n];
+    header[..N_LEN].copy_from_slice(&header_size_bytes);
+    file.read_exact(&mut header[N_LEN..])
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => SafeTensorError::InvalidHeaderLength.into(),
+            _ => CxxError::from(err),
+        })?;
+    Ok(header)
+}
+
+/// Start of `path` up to its payload, i.e. the length prefix and the JSON
+/// header, checked to parse. Nothing past the header is read, so querying
+/// a large file costs a few kilobytes of I/O. The bytes form a truncated
+/// file, which header queries such as `metadata`, `find`, `summarize` or
+/// `build_index`, as well as `deserialize_partial`, can be given directly.
+pub fn read_header_bytes(path: &str) -> Result<Vec<u8>, CxxError> {
+    let header = read_prefix_and_header(&mut File::open(path)?)?;
+    parse_header(&header[N_LEN..])?;
+    Ok(header)
+}
+
+/// Outputs written under a temporary name next to their destination and
+/// moved into place together by `commit`, so that neither a failure midway
+/// nor an output that is also an input leaves a destination half written.
+/// Temporary files not committed are removed on drop.
+#[derive(Default)]
+pub(crate) struct PendingFiles {
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PendingFiles {
+    /// Temporary path to write the future content of `path` to.
+    pub(crate) fn path(&mut self, path: &str) -> PathBuf {
+        let temp = PathBuf::from(format!("{path}.{}.tmp", std::process::id()));
+        self.files.push((temp.clone(), PathBuf::from(path)));
+        temp
+    }
+
+    /// Move every temporary file to its destination, in order.
+    pub(crate) fn commit(mut self) -> Result<(), CxxError> {
+        while let Some((temp, path)) = self.files.first() {
+            std::fs::rename(temp, path)?;
+            self.files.remove(0);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PendingFiles {
+    fn drop(&mut self) {
+        for (temp, _) in &self.files {
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+}
+
+/// Writer hashing every byte on its way to `inner`.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader hashing every byte on its way out of `inner`.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Same as `serialize_to_file`, also returning the lowercase hex SHA256 of
+/// the whole file, computed while writing it instead of reading it back.
+pub fn serialize_to_file_hashed(
+    data: Vec<PairStrTensorView>,
+    data_info: Vec<PairStrStr>,
+    path: &str,
+) -> Result<String, CxxError> {
+    let layout = layout(prepare(data)?, data_info, &SerializeOptions::default())?;
+    let mut writer = HashingWriter {
+        inner: BufWriter::new(File::create(path)?),
+        hasher: Sha256::new(),
+    };
+    write_layout(&layout, &mut writer)?;
+    writer.flush()?;
+
+    let digest = writer.hasher.finalize();
+    Ok(digest.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Stream a file laid out by `layout` to `writer`, which is not flushed.
+fn write_layout(layout: &Layout, writer: &mut impl Write) -> std::io::Result<()> {
+    writer.write_all(&(layout.header.len() as u64).to_le_bytes())?;
+    writer.write_all(&layout.header)?;
+    for data in &layout.payloads {
+        writer.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Byte counts of an export running through `serialize_to_file_progress`,
+/// readable from another thread while it runs.
+#[derive(Default)]
+pub struct SerializeProgress {
+    written: AtomicUsize,
+    total: AtomicUsize,
+}
+
+/// Fresh progress counter to pass to `serialize_to_file_progress`.
+pub fn new_serialize_progress() -> Box<SerializeProgress> {
+    Box::default()
+}
+
+impl SerializeProgress {
+    /// Bytes written to the file so far.
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Size of the whole file, known once the export has started.
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+}
+
+/// Writer adding every byte reaching `inner` to a progress counter.
+struct ProgressWriter<'p, W> {
+    inner: W,
+    progress: &'p SerializeProgress,
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.written.fetch_add(written, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Same as `serialize_to_file`, updating `progress` as chunks reach the
+/// file so a UI thread can poll it. Large tensors are accounted one by one,
+/// small ones together once the write buffer fills; the cost is one atomic
+/// add per write. There is no callback because cxx can't pass C++ function
+/// pointers or lambdas into Rust; `safetensors.hpp` has an overload taking
+/// one, which polls this counter from the calling thread.
+pub fn serialize_to_file_progress(
+    data: Vec<PairStrTensorView>,
+    data_info: Vec<PairStrStr>,
+    path: &str,
+    progress: &SerializeProgress,
+) -> Result<(), CxxError> {
+    let layout = layout(prepare(data)?, data_info, &SerializeOptions::default())?;
+    write_progress(&layout, File::create(path)?, progress)
+}
+
+/// Write `layout` to `inner`, counting bytes in `progress` as they reach it.
+fn write_progress(
+    layout: &Layout,
+    inner: impl Write,
+    progress: &SerializeProgress,
+) -> Result<(), CxxError> {
+    progress.written.store(0, Ordering::Relaxed);
+    progress.total.store(layout.file_len(), Ordering::Relaxed);
+    let mut writer = BufWriter::new(ProgressWriter { inner, progress });
+    write_layout(layout, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Load `in_path`, cast every floating point tensor to `target_dtype` and write
+/// the result to `out_path`. Integer and boolean tensors, as well as the
+/// `__metadata__` entries, are copied over untouched.
+pub fn transcode(in_path: &str, out_path: &str, target_dtype: Dtype) -> Result<(), CxxError> {
+    let target: RDtype = target_dtype.try_into()?;
+    if !cast::is_float(target) {
+        return Err(CxxError::UnsupportedDtype(target));
+    }
+
+    let buffer = std::fs::read(in_path)?;
+    let (n, metadata) = SafeTensors::read_metadata(&buffer)?;
+    let payload = &buffer[N_LEN + n..];
+
+    let mut tensors = Vec::with_capacity(metadata.tensors().len());
+    for (name, info) in metadata.tensors() {
+        let (start, end) = info.data_offsets;
+        let data = &payload[start..end];
+        let tensor = if cast::is_float(info.dtype) && info.dtype != target {
+            CowTensor {
+                dtype: target,
+                shape: info.shape.clone(),
+                data: Cow::Owned(cast::cast_float(info.dtype, target, data)?),
+            }
+        } else {
+            CowTensor {
+                dtype: info.dtype,
+                shape: info.shape.clone(),
+                data: Cow::Borrowed(data),
+            }
+        };
+        tensors.push((name, tensor));
+    }
+
+    safetensors::tensor::serialize_to_file(
+        tensors,
+        metadata.metadata().clone(),
+        out_path.as_ref(),
+    )?;
+    Ok(())
+}
+
+/// Read `path` with its tensors and `__metadata__` entries, parsing the
+/// header once for both.
+pub fn load_file_full(path: &str) -> Result<LoadedModel, CxxError> {
+    let buffer = std::fs::read(path)?;
+    let (n, metadata) = SafeTensors::read_metadata(&buffer)?;
+    let payload = &buffer[N_LEN + n..];
+
+    let tensors = metadata
+        .tensors()
+        .into_iter()
+        .map(|(name, info)| {
+            let (start, end) = info.data_offsets;
+            Ok(PairStrOwnedTensorView {
+                key: name,
+                value: OwnedTensorView {
+                    shape: bridge_shape(info.dtype, &info.shape),
+                    dtype: info.dtype.try_into()?,
+                    data: payload[start..end].to_vec(),
+                },
+            })
+        })
+        .collect::<Result<_, CxxError>>()?;
+    Ok(LoadedModel {
+        tensors,
+        metadata: metadata_pairs(&metadata),
+    })
+}
+
+/// Read the payload of `path` into one buffer, with the location of each
+/// tensor in it, in payload order. Meant for arena allocators: the buffer
+/// can be placed as a whole, tensors being referenced by offset.
+pub fn load_flat(path: &str) -> Result<FlatModel, CxxError> {
+    let (mut file, _, metadata) = read_header(path)?;
+    let mut payload = Vec::new();
+    file.read_to_end(&mut payload)?;
+
+    let tensors = tensor_locations(&metadata, 0)?;
+    let data_len = tensors.last().map_or(0, |t| t.offset + t.data_len);
+    if payload.len() != data_len {
+        return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+    }
+    Ok(FlatModel { payload, tensors })
+}
+
+/// Load the tensors of `path` after checking the file's SHA256 against
+/// `expected_sha256`, in hex of either case, e.g. from a model hub. The hash
+/// is computed while the file is read, and nothing is parsed on mismatch.
+pub fn load_file_verified(
+    path: &str,
+    expected_sha256: &str,
+) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = HashingReader {
+        inner: file,
+        hasher: Sha256::new(),
+    };
+    let mut buffer = Vec::with_capacity(size as usize);
+    reader.read_to_end(&mut buffer)?;
+
+    let digest: String = reader
+        .hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    if !digest.eq_ignore_ascii_case(expected_sha256) {
+        return Err(CxxError::ChecksumMismatch(
+            expected_sha256.to_string(),
+            digest,
+        ));
+    }
+    deserialize_owned(&buffer)
+}
+
+/// Same as `load_file_full` for untrusted inputs, e.g. uploads: the file
+/// size and the declared header length are checked against the limits
+/// before anything is allocated, so a crafted file can't make the loader
+/// reserve more than `max_total_bytes`. Only the tensors are returned.
+pub fn load_file_guarded(
+    path: &str,
+    max_header_bytes: usize,
+    max_total_bytes: usize,
+) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size > max_total_bytes as u64 {
+        return Err(CxxError::LimitExceeded("file", size, max_total_bytes));
+    }
+
+    let mut prefix = [0u8; N_LEN];
+    file.read_exact(&mut prefix)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => SafeTensorError::HeaderTooSmall.into(),
+            _ => CxxError::from(err),
+        })?;
+    let n = header_len(prefix)?;
+    if n > max_header_bytes {
+        return Err(CxxError::LimitExceeded(
+            "header",
+            n as u64,
+            max_header_bytes,
+        ));
+    }
+
+    // The file may have grown since it was measured, hence the `take`.
+    let mut buffer = Vec::with_capacity(size as usize);
+    buffer.extend(prefix);
+    file.take(max_total_bytes.saturating_sub(N_LEN) as u64 + 1)
+        .read_to_end(&mut buffer)?;
+    if buffer.len() > max_total_bytes {
+        return Err(CxxError::LimitExceeded(
+            "file",
+            buffer.len() as u64,
+            max_total_bytes,
+        ));
+    }
+    deserialize_owned(&buffer)
+}
+
+/// Recovery loader for files written with big-endian tensor data: every
+/// element wider than a byte is byte-swapped back to little-endian. The
+/// length prefix is accepted in either byte order.
+pub fn load_file_byteswap(path: &str) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+    let mut buffer = std::fs::read(path)?;
+    let Some(prefix) = buffer.first_chunk::<N_LEN>().copied() else {
+        return Err(SafeTensorError::HeaderTooSmall.into());
+    };
+    let fits = |n: Result<usize, SafeTensorError>| n.is_ok_and(|n| n <= buffer.len() - N_LEN);
+    if !fits(header_len(prefix)) {
+        let swapped = u64::from_be_bytes(prefix).to_le_bytes();
+        if fits(header_len(swapped)) {
+            buffer[..N_LEN].copy_from_slice(&swapped);
+        }
+    }
+
+    let mut tensors = deserialize_owned(&buffer)?;
+    for tensor in &mut tensors {
+        let width = RDtype::try_from(tensor.value.dtype)?.bitsize() / 8;
+        if width > 1 {
+            for element in tensor.value.data.chunks_exact_mut(width) {
+                element.reverse();
+            }
+        }
+    }
+    Ok(tensors)
+}
+
+/// Read rows `[row_start, row_end)` along the leading dimension of tensor
+/// `name`, fetching only the corresponding bytes from `path`.
+pub fn read_tensor_rows(
+    path: &str,
+    name: &str,
+    row_start: usize,
+    row_end: usize,
+) -> Result<OwnedTensorView, CxxError> {
+    let (mut file, n, metadata) = read_header(path)?;
+    let info = metadata
+        .info(name)
+        .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+
+    let Some((&rows, row_shape)) = info.shape.split_first() else {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    };
+    if row_start > row_end || row_end > rows {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+    let row_bits = row_shape
+        .iter()
+        .try_fold(info.dtype.bitsize(), |acc, &dim| acc.checked_mul(dim))
+        .ok_or(SafeTensorError::ValidationOverflow)?;
+    if row_bits % 8 != 0 {
+        return Err(SafeTensorError::MisalignedSlice.into());
+    }
+    let row_bytes = row_bits / 8;
+
+    let start = N_LEN + n + info.data_offsets.0 + row_start * row_bytes;
+    let mut data = vec![0u8; (row_end - row_start) * row_bytes];
+    file.seek(SeekFrom::Start(start as u64))?;
+    file.read_exact(&mut data)?;
+
+    let mut shape = info.shape.clone();
+    shape[0] = row_end - row_start;
+    Ok(OwnedTensorView {
+        shape: bridge_shape(info.dtype, &shape),
+        dtype: info.dtype.try_into()?,
+        data,
+    })
+}
+
+/// Write to `out_path` a file holding only tensors `names` of `in_path`,
+/// with `data_info` as its `__metadata__`. Only the bytes of those tensors
+/// are read. Fails if a name is missing or repeated.
+pub fn extract(
+    in_path: &str,
+    out_path: &str,
+    names: Vec<String>,
+    data_info: Vec<PairStrStr>,
+) -> Result<(), CxxError> {
+    let mut seen = HashSet::new();
+    if let Some(name) = names.iter().find(|&name| !seen.insert(name)) {
+        return Err(CxxError::DuplicateName(name.clone()));
+    }
+
+    let (mut file, n, metadata) = read_header(in_path)?;
+    let mut tensors = Vec::with_capacity(names.len());
+    for name in names {
+        let info = metadata
+            .info(&name)
+            .ok_or_else(|| SafeTensorError::TensorNotFound(name.clone()))?;
+        let (start, end) = info.data_offsets;
+        let mut data = vec![0u8; end - start];
+        file.seek(SeekFrom::Start((N_LEN + n + start) as u64))?;
+        file.read_exact(&mut data)?;
+        let tensor = CowTensor {
+            dtype: info.dtype,
+            shape: info.shape.clone(),
+            data: Cow::Owned(data),
+        };
+        tensors.push((name, tensor));
+    }
+    safetensors::tensor::serialize_to_file(
+        tensors,
+        convert_to_hashmap_string(data_info),
+        out_path.as_ref(),
+    )?;
+    Ok(())
+}
+
+/// Tensors of `a_path` and `b_path` that aren't `allclose`, e.g. a quantized
+/// model against its F32 source, as name and summary pairs sorted by name:
+/// `"3 of 6 values differ, max abs diff 0.5"`, a shape mismatch, or which
+/// file a tensor is missing from. Dtypes may differ. Tensors whose dtype
+/// can't be decoded, such as F4, must be byte for byte equal. Values where
+/// either side is NaN never compare equal and are counted on their own, e.g.
+/// `"2 of 6 values differ, 2 NaN"`. Both files are memory mapped and
+/// decoded one tensor at a time, so models larger than memory can be
+/// compared.
+pub fn compare_files(
+    a_path: &str,
+    b_path: &str,
+    rtol: f64,
+    atol: f64,
+) -> Result<Vec<PairStrStr>, CxxError> {
+    // SAFETY: the mappings are read-only and dropped before returning; the
+    // files must not be modified meanwhile, as with any mmap based loader.
+    let a_buffer = unsafe { Mmap::map(&File::open(a_path)?)? };
+    let b_buffer = unsafe { Mmap::map(&File::open(b_path)?)? };
+    let by_name = |buffer| -> Result<BTreeMap<_, _>, CxxError> {
+        Ok(crate::deserialize(buffer)?
+            .into_iter()
+            .map(|item| (item.key, item.value))
+            .collect())
+    };
+    let (a, b) = (by_name(&a_buffer)?, by_name(&b_buffer)?);
+
+    let names: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    let mut report = Vec::new();
+    for name in names {
+        let summary = match (a.get(name), b.get(name)) {
+            (Some(_), None) => format!("missing from {b_path}"),
+            (None, Some(_)) => format!("missing from {a_path}"),
+            (Some(x), Some(y)) if x.shape != y.shape => {
+                format!("shape {:?} vs {:?}", x.shape, y.shape)
+            }
+            (Some(x), Some(y)) => {
+                let decoded = cast::to_f64(x.dtype.try_into()?, x.data)
+                    .and_then(|xs| Ok((xs, cast::to_f64(y.dtype.try_into()?, y.data)?)));
+                let Ok((xs, ys)) = decoded else {
+                    if x.dtype != y.dtype || x.data != y.data {
+                        report.push(PairStrStr {
+                            key: name.clone(),
+                            value: "data differs".to_string(),
+                        });
+                    }
+                    continue;
+                };
+                // Same test as `allclose`, counting the values that fail it.
+                let (mut differ, mut nan, mut max_diff) = (0, 0, 0f64);
+                for (&x, &y) in xs.iter().zip(&ys) {
+                    if close(x, y, rtol, atol) {
+                        continue;
+                    }
+                    differ += 1;
+                    if x.is_nan() || y.is_nan() {
+                        nan += 1;
+                    } else {
+                        max_diff = max_diff.max((x - y).abs());
+                    }
+                }
+                if differ == 0 {
+                    continue;
+                }
+                let mut summary = format!("{differ} of {} values differ", xs.len());
+                if differ > nan {
+                    summary += &format!(", max abs diff {max_diff}");
+                }
+                if nan > 0 {
+                    summary += &format!(", {nan} NaN");
+                }
+                summary
+            }
+            (None, None) => unreachable!("names come from either file"),
+        };
+        report.push(PairStrStr {
+            key: name.clone(),
+            value: summary,
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_HEADER_SIZE;
+    use std::collections::HashMap;
+    use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+    #[test]
+    fn transcode_to_bf16() {
+        let weight: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let ids: Vec<u8> = [7i64, 8].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = vec![
+            (
+                "weight",
+                CowTensor {
+                    dtype: RDtype::F32,
+                    shape: vec![2, 2],
+                    data: Cow::Borrowed(&weight),
+                },
+            ),
+            (
+                "ids",
+                CowTensor {
+                    dtype: RDtype::I64,
+                    shape: vec![2],
+                    data: Cow::Borrowed(&ids),
+                },
+            ),
+        ];
+        let info = HashMap::from([("format".to_string(), "pt".to_string())]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let in_path = dir.join("transcode_in.safetensors");
+        let out_path = dir.join("transcode_out.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, Some(info), &in_path).unwrap();
+
+        transcode(
+            in_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            Dtype::BF16,
+        )
+        .unwrap();
+
+        let buffer = std::fs::read(&out_path).unwrap();
+        let loaded = SafeTensors::deserialize(&buffer).unwrap();
+        let weight = loaded.tensor("weight").unwrap();
+        assert_eq!(weight.dtype(), RDtype::BF16);
+        assert_eq!(weight.shape(), &[2, 2]);
+        assert_eq!(
+            cast::decode_f64(RDtype::BF16, weight.data()).unwrap(),
+            vec![1.0, 2.0, 3.0, 4.0]
+        );
+        let ids = loaded.tensor("ids").unwrap();
+        assert_eq!(ids.dtype(), RDtype::I64);
+        assert_eq!(
+            ids.data(),
+            &[7, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let (_, metadata) = SafeTensors::read_metadata(&buffer).unwrap();
+        assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+
+        assert!(transcode(
+            in_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            Dtype::I8,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn extract_subset() {
+        let tensors: Vec<_> = ["vision.patch", "vision.proj", "text.embed"]
+            .into_iter()
+            .map(|name| {
+                let tensor = CowTensor {
+                    dtype: RDtype::U8,
+                    shape: vec![name.len()],
+                    data: Cow::Owned(name.as_bytes().to_vec()),
+                };
+                (name, tensor)
+            })
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let in_path = dir.join("extract_in.safetensors");
+        let out_path = dir.join("extract_out.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &in_path).unwrap();
+        let (in_path, out_path) = (in_path.to_str().unwrap(), out_path.to_str().unwrap());
+
+        let names = vec!["vision.proj".to_string(), "vision.patch".to_string()];
+        let info = vec![PairStrStr {
+            key: "format".to_string(),
+            value: "pt".to_string(),
+        }];
+        extract(in_path, out_path, names, info).unwrap();
+        let buffer = std::fs::read(out_path).unwrap();
+        let loaded = SafeTensors::deserialize(&buffer).unwrap();
+        let mut names = loaded.names();
+        names.sort();
+        assert_eq!(names, vec!["vision.patch", "vision.proj"]);
+        assert_eq!(loaded.tensor("vision.proj").unwrap().data(), b"vision.proj");
+        let (_, metadata) = SafeTensors::read_metadata(&buffer).unwrap();
+        assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+
+        let missing = vec!["vision.patch".to_string(), "audio".to_string()];
+        assert!(matches!(
+            extract(in_path, out_path, missing, Vec::new()),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(name))) if name == "audio"
+        ));
+        let repeated = vec!["text.embed".to_string(), "text.embed".to_string()];
+        assert!(matches!(
+            extract(in_path, out_path, repeated, Vec::new()),
+            Err(CxxError::DuplicateName(_))
+        ));
+    }
+
+    #[test]
+    fn header_bytes() {
+        let data = vec![0u8; 1024];
+        let tensors = vec![(
+            "big",
+            CowTensor {
+                dtype: RDtype::U8,
+                shape: vec![1024],
+                data: Cow::Borrowed(&data),
+            },
+        )];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("read_header_bytes.safetensors");
+        let data_info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        safetensors::tensor::serialize_to_file(tensors, Some(data_info), &path).unwrap();
+        let file = std::fs::read(&path).unwrap();
+
+        let header = read_header_bytes(path.to_str().unwrap()).unwrap();
+        assert_eq!(header, file[..file.len() - data.len()]);
+        let index = crate::header::build_index(&header, true).unwrap();
+        assert_eq!(index[0].name, "big");
+        assert_eq!(index[0].offset, header.len());
+        let metadata = crate::metadata(&header).unwrap();
+        assert_eq!(
+            (metadata[0].key.as_str(), metadata[0].value.as_str()),
+            ("format", "pt")
+        );
+        assert!(crate::tensor_metadata(&header, "big").unwrap().is_empty());
+        assert_eq!(crate::header::find(&header, "b*").unwrap(), vec!["big"]);
+        let summary = crate::header::summarize(&header).unwrap();
+        assert_eq!((summary.num_tensors, summary.total_bytes), (1, 1024));
+        assert!(crate::header::check_shape(&header, "big", vec![1024]).unwrap());
+        assert_eq!(
+            crate::header::count_parameters(&header, false).unwrap(),
+            1024
+        );
+        assert!(crate::header::diff_headers(&header, &file)
+            .unwrap()
+            .is_empty());
+        assert!(crate::header::layout_info(&header).is_err());
+
+        let truncated = dir.join("read_header_bytes_truncated.safetensors");
+        std::fs::write(&truncated, &header[..header.len() - 1]).unwrap();
+        assert!(read_header_bytes(truncated.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn full_load() {
+        let ids: Vec<u8> = [7i64, 8].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = vec![(
+            "ids",
+            CowTensor {
+                dtype: RDtype::I64,
+                shape: vec![2],
+                data: Cow::Borrowed(&ids),
+            },
+        )];
+        let info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("load_file_full.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, Some(info), &path).unwrap();
+
+        let model = load_file_full(path.to_str().unwrap()).unwrap();
+        assert_eq!(model.tensors.len(), 1);
+        assert_eq!(model.tensors[0].key, "ids");
+        assert_eq!(model.tensors[0].value.shape, vec![2]);
+        assert_eq!(model.tensors[0].value.dtype, Dtype::I64);
+        assert_eq!(model.tensors[0].value.data, ids);
+        assert_eq!(model.metadata.len(), 1);
+        assert_eq!(model.metadata[0].key, "format");
+        assert_eq!(model.metadata[0].value, "pt");
+    }
+
+    #[test]
+    fn flat_load() {
+        let weight: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mask = [1u8, 0, 1];
+        let tensors = vec![
+            (
+                "mask",
+                CowTensor {
+                    dtype: RDtype::BOOL,
+                    shape: vec![3],
+                    data: Cow::Borrowed(&mask[..]),
+                },
+            ),
+            (
+                "weight",
+                CowTensor {
+                    dtype: RDtype::F32,
+                    shape: vec![2],
+                    data: Cow::Borrowed(&weight),
+                },
+            ),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("load_flat.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &path).unwrap();
+
+        let model = load_flat(path.to_str().unwrap()).unwrap();
+        assert_eq!(model.payload.len(), weight.len() + mask.len());
+        let names: Vec<_> = model.tensors.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["weight", "mask"]);
+        let mask_at = &model.tensors[1];
+        assert_eq!(
+            (mask_at.dtype, mask_at.shape.clone()),
+            (Dtype::BOOL, vec![3])
+        );
+        assert_eq!(
+            &model.payload[mask_at.offset..mask_at.offset + mask_at.data_len],
+            mask
+        );
+
+        let truncated = dir.join("load_flat_truncated.safetensors");
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&truncated, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(load_flat(truncated.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn compared_files() {
+        let f32s =
+            |values: &[f32]| -> Vec<u8> { values.iter().flat_map(|v| v.to_le_bytes()).collect() };
+        let tensor = |dtype, shape: Vec<usize>, data: Vec<u8>| CowTensor {
+            dtype,
+            shape,
+            data: Cow::Owned(data),
+        };
+        let source = vec![
+            (
+                "w",
+                tensor(RDtype::F32, vec![4], f32s(&[1.0, 2.0, 3.0, 4.0])),
+            ),
+            ("b", tensor(RDtype::F32, vec![2], f32s(&[0.5, 0.5]))),
+            ("s", tensor(RDtype::U8, vec![2, 1], vec![1, 2])),
+            ("dropped", tensor(RDtype::U8, vec![1], vec![0])),
+            (
+                "n",
+                tensor(RDtype::F32, vec![3], f32s(&[f32::NAN, 0.0, 1.0])),
+            ),
+            ("i", tensor(RDtype::F32, vec![1], f32s(&[1.0]))),
+        ];
+        let half: Vec<u8> = [1.0f32, 2.0, 3.5, 4.0]
+            .iter()
+            .flat_map(|&v| half::f16::from_f32(v).to_le_bytes())
+            .collect();
+        let converted = vec![
+            ("w", tensor(RDtype::F16, vec![4], half)),
+            ("b", tensor(RDtype::F32, vec![2], f32s(&[0.5, 0.5001]))),
+            (
+                "n",
+                tensor(RDtype::F32, vec![3], f32s(&[f32::NAN, f32::NAN, 1.0])),
+            ),
+            ("i", tensor(RDtype::F32, vec![1], f32s(&[f32::INFINITY]))),
+            ("s", tensor(RDtype::U8, vec![1, 2], vec![1, 2])),
+            ("added", tensor(RDtype::U8, vec![1], vec![0])),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let a = dir.join("compare_files_a.safetensors");
+        let b = dir.join("compare_files_b.safetensors");
+        safetensors::tensor::serialize_to_file(source, None, &a).unwrap();
+        safetensors::tensor::serialize_to_file(converted, None, &b).unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let report: Vec<_> = compare_files(a, b, 0.0, 1e-3)
+            .unwrap()
+            .into_iter()
+            .map(|pair| (pair.key, pair.value))
+            .collect();
+        assert_eq!(
+            report,
+            [
+                ("added".to_string(), format!("missing from {a}")),
+                ("dropped".to_string(), format!("missing from {b}")),
+                (
+                    "i".to_string(),
+                    "1 of 1 values differ, max abs diff inf".to_string()
+                ),
+                ("n".to_string(), "2 of 3 values differ, 2 NaN".to_string()),
+                ("s".to_string(), "shape [2, 1] vs [1, 2]".to_string()),
+                (
+                    "w".to_string(),
+                    "1 of 4 values differ, max abs diff 0.5".to_string()
+                ),
+            ]
+        );
+        // NaN is not close to itself, as in NumPy.
+        let same = compare_files(a, a, 0.0, 0.0).unwrap();
+        assert_eq!(same.len(), 1);
+        assert_eq!(
+            (same[0].key.as_str(), same[0].value.as_str()),
+            ("n", "1 of 3 values differ, 1 NaN")
+        );
+    }
+
+    #[test]
+    fn guarded_load() {
+        let data = vec![0u8; 64];
+        let tensors = vec![(
+            "weight",
+            CowTensor {
+                dtype: RDtype::U8,
+                shape: vec![64],
+                data: Cow::Borrowed(&data),
+            },
+        )];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("load_file_guarded.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &path).unwrap();
+        let path = path.to_str().unwrap();
+        let size = std::fs::metadata(path).unwrap().len() as usize;
+        let n = size - N_LEN - data.len();
+
+        let loaded = load_file_guarded(path, n, size).unwrap();
+        assert_eq!(loaded[0].key, "weight");
+        assert_eq!(loaded[0].value.data, data);
+
+        assert!(matches!(
+            load_file_guarded(path, n, size - 1),
+            Err(CxxError::LimitExceeded("file", s, _)) if s == size as u64
+        ));
+        assert!(matches!(
+            load_file_guarded(path, n - 1, size),
+            Err(CxxError::LimitExceeded("header", s, _)) if s == n as u64
+        ));
+
+        // A header claiming far more than the file holds.
+        let mut huge = std::fs::read(path).unwrap();
+        huge[..N_LEN].copy_from_slice(&(MAX_HEADER_SIZE as u64).to_le_bytes());
+        let huge_path = dir.join("load_file_guarded_huge.safetensors");
+        std::fs::write(&huge_path, huge).unwrap();
+        assert!(matches!(
+            load_file_guarded(huge_path.to_str().unwrap(), 1024, size),
+            Err(CxxError::LimitExceeded("header", ..))
+        ));
+    }
+
+    #[test]
+    fn hashed_file() {
+        let data: Vec<u8> = (0..6u16).flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = || {
+            vec![PairStrTensorView {
+                key: "weight".to_string(),
+                value: crate::ffi::TensorView {
+                    shape: vec![2, 3],
+                    dtype: Dtype::U16,
+                    data: &data,
+                    data_len: data.len(),
+                },
+            }]
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("serialize_to_file_hashed.safetensors");
+        let digest =
+            serialize_to_file_hashed(tensors(), Vec::new(), path.to_str().unwrap()).unwrap();
+
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(written, crate::serialize(tensors(), Vec::new()).unwrap());
+        let expected: String = Sha256::digest(&written)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(digest, expected);
+        assert_eq!(digest.len(), 64);
+
+        // Tensors are validated before the file is created, so a bad one
+        // leaves the previous export in place.
+        let mut invalid = tensors();
+        invalid[0].value.shape = vec![4, 3];
+        assert!(serialize_to_file_hashed(invalid, Vec::new(), path.to_str().unwrap()).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+    }
+
+    #[test]
+    fn verified_load() {
+        let data: Vec<u8> = (0..6u16).flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = vec![PairStrTensorView {
+            key: "weight".to_string(),
+            value: crate::ffi::TensorView {
+                shape: vec![2, 3],
+                dtype: Dtype::U16,
+                data: &data,
+                data_len: data.len(),
+            },
+        }];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("load_file_verified.safetensors");
+        let path = path.to_str().unwrap();
+        let digest = serialize_to_file_hashed(tensors, Vec::new(), path).unwrap();
+
+        let loaded = load_file_verified(path, &digest.to_uppercase()).unwrap();
+        assert_eq!(loaded[0].key, "weight");
+        assert_eq!(loaded[0].value.data, data);
+
+        let mut corrupted = std::fs::read(path).unwrap();
+        *corrupted.last_mut().unwrap() ^= 1;
+        std::fs::write(path, corrupted).unwrap();
+        assert!(matches!(
+            load_file_verified(path, &digest),
+            Err(CxxError::ChecksumMismatch(expected, actual))
+                if expected == digest && actual != digest
+        ));
+    }
+
+    #[test]
+    fn byteswap_load() {
+        let floats: Vec<u8> = [1.5f32, -2.0]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let halves: Vec<u8> = [0x3c00u16, 0xc000]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let bytes = [1u8, 2, 3];
+        let tensors = vec![
+            ("floats", RDtype::F32, &floats[..]),
+            ("halves", RDtype::F16, &halves[..]),
+            ("bytes", RDtype::U8, &bytes[..]),
+        ];
+        let tensors: Vec<_> = tensors
+            .into_iter()
+            .map(|(name, dtype, data)| {
+                let tensor = CowTensor {
+                    dtype,
+                    shape: vec![data.len() * 8 / dtype.bitsize()],
+                    data: Cow::Borrowed(data),
+                };
+                (name, tensor)
+            })
+            .collect();
+        let mut buffer = safetensors::serialize(tensors, None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("byteswap_load.safetensors");
+        std::fs::write(&path, &buffer).unwrap();
+
+        let check = |path: &std::path::Path| {
+            let loaded = load_file_byteswap(path.to_str().unwrap()).unwrap();
+            let get = |name| &loaded.iter().find(|t| t.key == name).unwrap().value.data;
+            assert_eq!(
+                get("floats"),
+                &[1.5f32, -2.0]
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(get("halves"), &[0x00, 0x3c, 0x00, 0xc0]);
+            assert_eq!(get("bytes"), &bytes);
+        };
+        check(&path);
+
+        // Same file with a big-endian length prefix.
+        let n = u64::from_le_bytes(buffer[..N_LEN].try_into().unwrap());
+        buffer[..N_LEN].copy_from_slice(&n.to_be_bytes());
+        std::fs::write(&path, &buffer).unwrap();
+        check(&path);
+    }
+
+    #[test]
+    fn progress_reports() {
+        let weight = vec![1u8; 64 * 1024];
+        let tensors = || {
+            vec![PairStrTensorView {
+                key: "weight".to_string(),
+                value: crate::ffi::TensorView {
+                    shape: vec![weight.len()],
+                    dtype: Dtype::U8,
+                    data: &weight,
+                    data_len: weight.len(),
+                },
+            }]
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("serialize_to_file_progress.safetensors");
+        let progress = new_serialize_progress();
+        assert_eq!((progress.written(), progress.total()), (0, 0));
+
+        serialize_to_file_progress(tensors(), Vec::new(), path.to_str().unwrap(), &progress)
+            .unwrap();
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        assert_eq!(progress.total(), size);
+        assert_eq!(progress.written(), size);
+
+        // Writer pausing before each write until the test thread has read
+        // the counter, so every reading is taken while the export runs.
+        struct Stepped {
+            about_to_write: SyncSender<()>,
+            resume: Receiver<()>,
+        }
+        impl Write for Stepped {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.about_to_write.send(()).unwrap();
+                self.resume.recv().unwrap();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let layout = layout(prepare(tensors()).unwrap(), Vec::new(), &Default::default()).unwrap();
+        let (about_to_write, writes) = sync_channel(0);
+        let (resume, resumed) = sync_channel(0);
+        let writer = Stepped {
+            about_to_write,
+            resume: resumed,
+        };
+        let progress = new_serialize_progress();
+        let readings = std::thread::scope(|scope| {
+            let export = scope.spawn(|| write_progress(&layout, writer, &progress));
+            let mut readings = Vec::new();
+            for () in writes {
+                readings.push(progress.written());
+                resume.send(()).unwrap();
+            }
+            export.join().unwrap().unwrap();
+            readings
+        });
+        let total = layout.file_len();
+        assert_eq!(progress.written(), total);
+        // The header goes out on its own, then the tensor bypasses the
+        // write buffer, which is smaller.
+        assert_eq!(readings, vec![0, total - weight.len()]);
+        assert!(readings.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(readings
+            .iter()
+            .any(|&written| 0 < written && written < total));
+    }
+
+    #[test]
+    fn read_rows() {
+        let data: Vec<u8> = (0..12u32).flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = vec![(
+            "embedding",
+            CowTensor {
+                dtype: RDtype::U32,
+                shape: vec![4, 3],
+                data: Cow::Borrowed(&data),
+            },
+        )];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("read_rows.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &path).unwrap();
+        let path = path.to_str().unwrap();
+
+        let rows = read_tensor_rows(path, "embedding", 1, 3).unwrap();
+        assert_eq!(rows.shape, vec![2, 3]);
+        assert_eq!(rows.dtype, Dtype::U32);
+        assert_eq!(rows.data, data[12..36]);
+
+        let empty = read_tensor_rows(path, "embedding", 4, 4).unwrap();
+        assert_eq!(empty.shape, vec![0, 3]);
+        assert!(empty.data.is_empty());
+
+        assert!(matches!(
+            read_tensor_rows(path, "embedding", 2, 5),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::TensorInvalidInfo
+            ))
+        ));
+        assert!(matches!(
+            read_tensor_rows(path, "missing", 0, 1),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                _
+            )))
+        ));
+    }
+}
@@ -0,0 +1,1269 @@
+//! Operations reading or rewriting the header only, the payload bytes being
+//! copied over verbatim.
+use crate::error::CxxError;
+use crate::ffi::{
+    Dtype, LayoutInfo, ModelSummary, PairDtypeUsize, PairStrStr, PairStrUsize, TensorLocation,
+    TensorSpec,
+};
+use crate::{
+    bridge_shape, convert_to_hashmap_string, header_len, normalize_shape, partial_header,
+    split_header, view_size, METADATA_KEY, N_LEN,
+};
+use safetensors::tensor::{Metadata, TensorInfo};
+use safetensors::Dtype as RDtype;
+use safetensors::{SafeTensorError, SafeTensors};
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Replace every `.` and `/` in tensor names with `separator`, e.g. to turn
+/// TensorFlow style `dense/kernel` and PyTorch style `dense.kernel` into the
+/// same name. Fails if two tensors end up with the same name.
+pub fn normalize_names(buffer: &[u8], separator: &str) -> Result<Vec<u8>, CxxError> {
+    rename_tensors(buffer, |name| name.replace(['.', '/'], separator))
+}
+
+/// Union of two `__metadata__` maps. On a key present in both, the value
+/// from `a` is kept if `prefer_first`, the one from `b` otherwise. Keys keep
+/// the order they first appear in, `a` before `b`. `concat_files_to_file`
+/// folds each shard's `__metadata__` through it with `prefer_first`.
+pub fn merge_metadata(
+    a: Vec<PairStrStr>,
+    b: Vec<PairStrStr>,
+    prefer_first: bool,
+) -> Vec<PairStrStr> {
+    let mut merged: Vec<PairStrStr> = Vec::with_capacity(a.len() + b.len());
+    let mut positions = HashMap::new();
+    for item in a.into_iter().chain(b) {
+        match positions.get(&item.key) {
+            Some(&i) if !prefer_first => merged[i] = item,
+            Some(_) => {}
+            None => {
+                positions.insert(item.key.clone(), merged.len());
+                merged.push(item);
+            }
+        }
+    }
+    merged
+}
+
+/// Cheap content sniff: the length prefix is sane, the declared header fits
+/// in `buffer` and starts with `{`. The JSON itself is not parsed.
+pub fn is_safetensors(buffer: &[u8]) -> bool {
+    let Some((prefix, rest)) = buffer.split_first_chunk::<N_LEN>() else {
+        return false;
+    };
+    match header_len(*prefix) {
+        Ok(n) => (2..=rest.len()).contains(&n) && rest[0] == b'{',
+        Err(_) => false,
+    }
+}
+
+/// Top-level keys of a JSON header in the order they are written, which the
+/// `HashMap` used by `safetensors` does not keep. Values are skipped.
+pub(crate) fn header_keys(header_bytes: &[u8]) -> Result<Vec<String>, SafeTensorError> {
+    struct KeysVisitor;
+
+    impl<'de> Visitor<'de> for KeysVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a safetensors header")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut keys = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(key) = map.next_key::<String>()? {
+                map.next_value::<IgnoredAny>()?;
+                keys.push(key);
+            }
+            Ok(keys)
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_slice(header_bytes);
+    deserializer
+        .deserialize_map(KeysVisitor)
+        .map_err(SafeTensorError::InvalidHeaderDeserialization)
+}
+
+/// Tensor entries of a JSON header in the order they are written, skipping
+/// `__metadata__`. Unlike `Metadata`, offsets are not validated at all.
+pub(crate) fn header_tensors(
+    header_bytes: &[u8],
+) -> Result<Vec<(String, TensorInfo)>, SafeTensorError> {
+    struct TensorsVisitor;
+
+    impl<'de> Visitor<'de> for TensorsVisitor {
+        type Value = Vec<(String, TensorInfo)>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a safetensors header")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut tensors = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(key) = map.next_key::<String>()? {
+                if key == METADATA_KEY {
+                    map.next_value::<IgnoredAny>()?;
+                } else {
+                    tensors.push((key, map.next_value::<TensorInfo>()?));
+                }
+            }
+            Ok(tensors)
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_slice(header_bytes);
+    deserializer
+        .deserialize_map(TensorsVisitor)
+        .map_err(SafeTensorError::InvalidHeaderDeserialization)
+}
+
+/// Assemble a file from an already validated header and its payload,
+/// padding the header to 8 bytes like `safetensors::serialize` does.
+pub(crate) fn write_header(
+    metadata: &impl serde::Serialize,
+    payload: &[u8],
+) -> Result<Vec<u8>, CxxError> {
+    write_header_aligned(metadata, payload, N_LEN)
+}
+
+/// Same as `write_header`, padding the header so the payload starts at a
+/// multiple of `align` bytes in the file, itself a multiple of 8.
+fn write_header_aligned(
+    metadata: &impl serde::Serialize,
+    payload: &[u8],
+    align: usize,
+) -> Result<Vec<u8>, CxxError> {
+    let mut header = serde_json::to_string(metadata)
+        .map_err(safetensors::SafeTensorError::JsonError)?
+        .into_bytes();
+    header.resize((N_LEN + header.len()).next_multiple_of(align) - N_LEN, b' ');
+
+    let mut out = Vec::with_capacity(N_LEN + header.len() + payload.len());
+    out.extend((header.len() as u64).to_le_bytes());
+    out.extend(header);
+    out.extend(payload);
+    Ok(out)
+}
+
+/// Rename every tensor of `buffer` through `rename`, leaving offsets and
+/// payload untouched. Fails if two tensors end up with the same name, or if
+/// a new name is empty or `__metadata__`.
+pub(crate) fn rename_tensors(
+    buffer: &[u8],
+    rename: impl Fn(&str) -> String,
+) -> Result<Vec<u8>, CxxError> {
+    let (n, metadata) = SafeTensors::read_metadata(buffer)?;
+
+    let mut seen = HashSet::new();
+    let mut tensors = Vec::with_capacity(metadata.tensors().len());
+    for name in metadata.offset_keys() {
+        let info = metadata
+            .info(&name)
+            .expect("offset_keys come from the metadata");
+        let new_name = rename(&name);
+        if new_name == METADATA_KEY {
+            return Err(CxxError::ReservedName(new_name));
+        }
+        if new_name.is_empty() {
+            return Err(SafeTensorError::TensorInvalidInfo.into());
+        }
+        if !seen.insert(new_name.clone()) {
+            return Err(CxxError::DuplicateName(new_name));
+        }
+        tensors.push((new_name, info.clone()));
+    }
+
+    let renamed = Metadata::new(metadata.metadata().clone(), tensors)?;
+    write_header(&renamed, &buffer[N_LEN + n..])
+}
+
+/// Remove `prefix` from every tensor name starting with it, e.g. the
+/// `module.` added by DataParallel. Other names are kept as is.
+pub fn strip_prefix(buffer: &[u8], prefix: &str) -> Result<Vec<u8>, CxxError> {
+    rename_tensors(buffer, |name| {
+        name.strip_prefix(prefix).unwrap_or(name).to_string()
+    })
+}
+
+/// Prepend `prefix` to every tensor name.
+pub fn add_prefix(buffer: &[u8], prefix: &str) -> Result<Vec<u8>, CxxError> {
+    rename_tensors(buffer, |name| format!("{prefix}{name}"))
+}
+
+/// Location of every tensor of `metadata` in payload order, `base` being
+/// added to each offset.
+pub(crate) fn tensor_locations(
+    metadata: &Metadata,
+    base: usize,
+) -> Result<Vec<TensorLocation>, CxxError> {
+    metadata
+        .offset_keys()
+        .into_iter()
+        .map(|name| {
+            let info = metadata
+                .info(&name)
+                .expect("offset_keys come from the metadata");
+            let (start, end) = info.data_offsets;
+            Ok(TensorLocation {
+                shape: bridge_shape(info.dtype, &info.shape),
+                dtype: info.dtype.try_into()?,
+                offset: base + start,
+                data_len: end - start,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Location of every tensor of `buffer`, in payload order. Offsets are
+/// relative to the payload, or with `relative_to_file` to the start of the
+/// file, header included, so C++ can `pread` at them as is. Only the header
+/// has to be in `buffer`.
+pub fn build_index(buffer: &[u8], relative_to_file: bool) -> Result<Vec<TensorLocation>, CxxError> {
+    let (payload_start, metadata) = partial_header(buffer)?;
+    let base = if relative_to_file { payload_start } else { 0 };
+    tensor_locations(&metadata, base)
+}
+
+/// Copy of `buffer` without tensors `names`, e.g. optimizer state or an
+/// `lm_head` stripped before deployment. The remaining tensors keep their
+/// order and are moved up so the payload has no gap. `__metadata__` is kept.
+/// Fails with `TensorNotFound` if a name has no tensor.
+pub fn remove_tensors(buffer: &[u8], names: Vec<String>) -> Result<Vec<u8>, CxxError> {
+    let (n, metadata) = SafeTensors::read_metadata(buffer)?;
+    if let Some(name) = names.iter().find(|name| metadata.info(name).is_none()) {
+        return Err(SafeTensorError::TensorNotFound(name.clone()).into());
+    }
+    let removed: HashSet<String> = names.into_iter().collect();
+
+    let payload = &buffer[N_LEN + n..];
+    let mut kept = Vec::new();
+    let mut tensors = Vec::new();
+    for name in metadata.offset_keys() {
+        if removed.contains(&name) {
+            continue;
+        }
+        let info = metadata
+            .info(&name)
+            .expect("offset_keys come from the metadata");
+        let (start, end) = info.data_offsets;
+        let offset = kept.len();
+        kept.extend_from_slice(&payload[start..end]);
+        let info = TensorInfo {
+            dtype: info.dtype,
+            shape: info.shape.clone(),
+            data_offsets: (offset, kept.len()),
+        };
+        tensors.push((name, info));
+    }
+
+    let metadata = Metadata::new(metadata.metadata().clone(), tensors)?;
+    write_header(&metadata, &kept)
+}
+
+/// Fresh header, length prefix included, for a payload holding `tensors`
+/// back to back in the given order. Fails unless their sizes add up to
+/// exactly `payload_len`, so the result can be prepended to the payload.
+pub fn rebuild_header(
+    tensors: Vec<TensorSpec>,
+    data_info: Vec<PairStrStr>,
+    payload_len: usize,
+) -> Result<Vec<u8>, CxxError> {
+    let mut seen = HashSet::new();
+    let mut infos = Vec::with_capacity(tensors.len());
+    let mut offset = 0usize;
+    for tensor in tensors {
+        if tensor.name == METADATA_KEY {
+            return Err(CxxError::ReservedName(tensor.name));
+        }
+        if !seen.insert(tensor.name.clone()) {
+            return Err(CxxError::DuplicateName(tensor.name));
+        }
+        let dtype = tensor.dtype.try_into()?;
+        let shape = normalize_shape(dtype, &tensor.shape);
+        let end = offset
+            .checked_add(view_size(dtype, &shape)?)
+            .ok_or(SafeTensorError::ValidationOverflow)?;
+        let info = TensorInfo {
+            dtype,
+            shape,
+            data_offsets: (offset, end),
+        };
+        infos.push((tensor.name, info));
+        offset = end;
+    }
+    if offset != payload_len {
+        return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+    }
+
+    let metadata = Metadata::new(convert_to_hashmap_string(data_info), infos)?;
+    write_header(&metadata, &[])
+}
+
+/// File holding `payload` as is, with a header placing each tensor at the
+/// offsets given in `entries` rather than assigning them, e.g. to rewrite a
+/// header without moving a single tensor. `entries` may come in any order,
+/// but their ranges must tile `payload` exactly, each as large as its dtype
+/// and shape call for.
+pub fn serialize_with_offsets(
+    entries: Vec<TensorLocation>,
+    payload: &[u8],
+    data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    let mut seen = HashSet::new();
+    let mut infos = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if entry.name == METADATA_KEY {
+            return Err(CxxError::ReservedName(entry.name));
+        }
+        if !seen.insert(entry.name.clone()) {
+            return Err(CxxError::DuplicateName(entry.name));
+        }
+        let dtype = entry.dtype.try_into()?;
+        let end = entry
+            .offset
+            .checked_add(entry.data_len)
+            .ok_or(SafeTensorError::ValidationOverflow)?;
+        let info = TensorInfo {
+            dtype,
+            shape: normalize_shape(dtype, &entry.shape),
+            data_offsets: (entry.offset, end),
+        };
+        infos.push((entry.name, info));
+    }
+    infos.sort_by_key(|(_, info)| info.data_offsets);
+    if infos.last().map_or(0, |(_, info)| info.data_offsets.1) != payload.len() {
+        return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+    }
+
+    // Checks that the ranges are contiguous and match dtypes and shapes.
+    let metadata = Metadata::new(convert_to_hashmap_string(data_info), infos)?;
+    write_header(&metadata, payload)
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of
+/// characters, dots included, and `?` exactly one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and of the name when it was reached, to
+    // backtrack to when a later literal fails to match.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Names of the tensors matching the glob `pattern`, e.g.
+/// `*.attention.*`, in the order their data is laid out.
+pub fn find(buffer: &[u8], pattern: &str) -> Result<Vec<String>, CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    Ok(metadata
+        .offset_keys()
+        .into_iter()
+        .filter(|name| glob_match(pattern, name))
+        .collect())
+}
+
+/// Names of `expected` with no tensor in `buffer`, in the order given, e.g.
+/// to report every layer a checkpoint lacks against an architecture spec.
+pub fn find_missing(buffer: &[u8], expected: Vec<String>) -> Result<Vec<String>, CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    Ok(expected
+        .into_iter()
+        .filter(|name| metadata.info(name).is_none())
+        .collect())
+}
+
+/// Tensors of `buffer` not listed in `expected`, in payload order.
+pub fn find_unexpected(buffer: &[u8], expected: Vec<String>) -> Result<Vec<String>, CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    let expected: HashSet<String> = expected.into_iter().collect();
+    Ok(metadata
+        .offset_keys()
+        .into_iter()
+        .filter(|name| !expected.contains(name))
+        .collect())
+}
+
+/// Whether tensor `name` has shape `expected`, as seen from C++. Fails with
+/// `TensorNotFound` if there is no such tensor.
+pub fn check_shape(buffer: &[u8], name: &str, expected: Vec<usize>) -> Result<bool, CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    let info = metadata
+        .info(name)
+        .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+    Ok(bridge_shape(info.dtype, &info.shape) == expected)
+}
+
+/// Tensor count, payload size and dtypes of a file, from one parse of its
+/// header, e.g. for a `model info` command.
+pub fn summarize(buffer: &[u8]) -> Result<ModelSummary, CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    let tensors = metadata.tensors();
+
+    let mut dtype_counts = BTreeMap::new();
+    let mut total_bytes = 0;
+    for info in tensors.values() {
+        *dtype_counts
+            .entry(Dtype::try_from(info.dtype)?)
+            .or_insert(0) += 1;
+        total_bytes += info.data_offsets.1 - info.data_offsets.0;
+    }
+    Ok(ModelSummary {
+        num_tensors: tensors.len(),
+        total_bytes,
+        dtype_counts: dtype_counts
+            .into_iter()
+            .map(|(key, value)| PairDtypeUsize { key, value })
+            .collect(),
+        has_metadata: metadata.metadata().is_some(),
+    })
+}
+
+/// Sizes of the header and payload of `buffer`, e.g. to report that a
+/// header is 2MB of a 13GB file. The buffer is validated like `deserialize`
+/// does, so the two add up to its length.
+pub fn layout_info(buffer: &[u8]) -> Result<LayoutInfo, CxxError> {
+    let (n, _) = SafeTensors::read_metadata(buffer)?;
+    Ok(LayoutInfo {
+        header_bytes: N_LEN + n,
+        payload_bytes: buffer.len() - N_LEN - n,
+        total_bytes: buffer.len(),
+    })
+}
+
+/// Number of elements across the tensors of `buffer`, the familiar "7B
+/// parameters" figure. With `float_only`, only floating point tensors are
+/// counted, leaving out integer buffers such as position ids, boolean masks
+/// and F8_E8M0 scales. Each F4 value counts as one parameter.
+pub fn count_parameters(buffer: &[u8], float_only: bool) -> Result<usize, CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    Ok(metadata
+        .tensors()
+        .values()
+        .filter(|info| {
+            !float_only
+                || matches!(
+                    info.dtype,
+                    RDtype::F4
+                        | RDtype::F6_E2M3
+                        | RDtype::F6_E3M2
+                        | RDtype::F8_E5M2
+                        | RDtype::F8_E4M3
+                        | RDtype::F16
+                        | RDtype::BF16
+                        | RDtype::F32
+                        | RDtype::F64
+                )
+        })
+        .map(|info| info.shape.iter().product::<usize>())
+        .sum())
+}
+
+/// Fail unless every tensor of `buffer` has dtype `dtype`, e.g. for a
+/// runtime only supporting F32. The error lists each offending tensor with
+/// its dtype, in payload order.
+pub fn require_dtype(buffer: &[u8], dtype: Dtype) -> Result<(), CxxError> {
+    let (_, metadata) = partial_header(buffer)?;
+    let expected = dtype.try_into()?;
+    let offending: Vec<_> = metadata
+        .offset_keys()
+        .into_iter()
+        .filter_map(|name| {
+            let info = metadata.info(&name)?;
+            (info.dtype != expected).then(|| format!("{name} ({})", info.dtype))
+        })
+        .collect();
+    if offending.is_empty() {
+        Ok(())
+    } else {
+        Err(CxxError::UnexpectedDtypes(expected, offending.join(", ")))
+    }
+}
+
+/// Bytes left unused before each tensor, in payload order: the gap between
+/// the end of the previous tensor, or the start of the payload, and its
+/// first byte. The header is read without the contiguity check `safetensors`
+/// enforces, which rejects any nonzero gap, so files padded by other writers
+/// can be inspected too.
+pub fn padding_report(buffer: &[u8]) -> Result<Vec<PairStrUsize>, CxxError> {
+    let Some((prefix, rest)) = buffer.split_first_chunk::<N_LEN>() else {
+        return Err(SafeTensorError::HeaderTooSmall.into());
+    };
+    let n = header_len(*prefix)?;
+    let header = rest.get(..n).ok_or(SafeTensorError::InvalidHeaderLength)?;
+    let entries: HashMap<String, serde_json::Value> =
+        serde_json::from_slice(header).map_err(SafeTensorError::InvalidHeaderDeserialization)?;
+
+    let mut tensors = Vec::with_capacity(entries.len());
+    for (name, value) in entries {
+        if name == METADATA_KEY {
+            continue;
+        }
+        let info: TensorInfo =
+            serde_json::from_value(value).map_err(SafeTensorError::InvalidHeaderDeserialization)?;
+        tensors.push((name, info.data_offsets));
+    }
+    tensors.sort_by_key(|(_, offsets)| *offsets);
+
+    let mut end = 0;
+    let mut report = Vec::with_capacity(tensors.len());
+    for (name, (start, stop)) in tensors {
+        let Some(padding) = start.checked_sub(end).filter(|_| stop >= start) else {
+            return Err(SafeTensorError::InvalidOffset(name).into());
+        };
+        report.push(PairStrUsize {
+            key: name,
+            value: padding,
+        });
+        end = stop;
+    }
+    Ok(report)
+}
+
+/// Fix files whose last tensor ends short of the payload, as written by
+/// some broken exporters, by extending its end offset when the bytes left
+/// over are exactly the ones its dtype and shape call for. Valid buffers are
+/// returned unchanged. Any other inconsistency, including trailing bytes no
+/// tensor accounts for, fails with the error `safetensors` would raise.
+pub fn try_repair(buffer: &[u8]) -> Result<Vec<u8>, CxxError> {
+    let err = match SafeTensors::deserialize(buffer) {
+        Ok(_) => return Ok(buffer.to_vec()),
+        Err(err) => err,
+    };
+    let (header, payload) = split_header(buffer)?;
+    let (data_info, mut tensors) = lenient_entries(header)?;
+
+    let Some((_, last)) = tensors.last_mut() else {
+        return Err(err.into());
+    };
+    let (start, end) = last.data_offsets;
+    let repaired_end = view_size(last.dtype, &last.shape)
+        .ok()
+        .and_then(|size| start.checked_add(size))
+        .filter(|&repaired| repaired == payload.len() && repaired > end);
+    let Some(repaired_end) = repaired_end else {
+        return Err(err.into());
+    };
+    last.data_offsets.1 = repaired_end;
+
+    // Anything else wrong with the header is rejected here.
+    let metadata = Metadata::new(data_info, tensors).map_err(|_| err)?;
+    write_header(&metadata, payload)
+}
+
+/// `__metadata__` and tensors of a JSON header, the latter sorted by offsets,
+/// without any of the consistency checks `Metadata::new` makes.
+fn lenient_entries(header: &[u8]) -> Result<LenientEntries, SafeTensorError> {
+    let entries: HashMap<String, serde_json::Value> =
+        serde_json::from_slice(header).map_err(SafeTensorError::InvalidHeaderDeserialization)?;
+
+    let mut data_info = None;
+    let mut tensors = Vec::with_capacity(entries.len());
+    for (name, value) in entries {
+        if name == METADATA_KEY {
+            data_info = serde_json::from_value(value)
+                .map_err(SafeTensorError::InvalidHeaderDeserialization)?;
+        } else {
+            let info: TensorInfo = serde_json::from_value(value)
+                .map_err(SafeTensorError::InvalidHeaderDeserialization)?;
+            tensors.push((name, info));
+        }
+    }
+    tensors.sort_by_key(|(_, info)| info.data_offsets);
+    Ok((data_info, tensors))
+}
+
+type LenientEntries = (Option<HashMap<String, String>>, Vec<(String, TensorInfo)>);
+
+/// Rewrite `buffer` with its tensors back to back and the header padded so
+/// the payload starts at a multiple of `payload_align` bytes in the file,
+/// e.g. 4096 for DMA transfers, keeping tensor order and `__metadata__`.
+/// Like `SerializeOptions::align`, only the first tensor is guaranteed to be
+/// aligned: `safetensors` rejects any gap between tensors, so they can't be
+/// padded individually. Gaps left by other writers are closed up, which
+/// turns such files into regular ones, as long as tensors don't overlap.
+/// `payload_align` must be 0 or a power of two, 0 keeping the usual 8 bytes.
+pub fn repack(buffer: &[u8], payload_align: usize) -> Result<Vec<u8>, CxxError> {
+    if !(payload_align == 0 || payload_align.is_power_of_two()) {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+    let (header, payload) = split_header(buffer)?;
+    let (data_info, tensors) = lenient_entries(header)?;
+
+    let mut out_tensors = Vec::with_capacity(tensors.len());
+    let mut out_payload = Vec::with_capacity(payload.len());
+    let mut end = 0;
+    for (name, mut info) in tensors {
+        let (start, stop) = info.data_offsets;
+        let data = payload
+            .get(start..stop)
+            .filter(|_| start >= end)
+            .ok_or_else(|| SafeTensorError::InvalidOffset(name.clone()))?;
+        if view_size(info.dtype, &info.shape)? != data.len() {
+            return Err(SafeTensorError::TensorInvalidInfo.into());
+        }
+        end = stop;
+
+        info.data_offsets = (out_payload.len(), out_payload.len() + data.len());
+        out_payload.extend_from_slice(data);
+        out_tensors.push((name, info));
+    }
+    let metadata = Metadata::new(data_info, out_tensors)?;
+    write_header_aligned(&metadata, &out_payload, payload_align.max(N_LEN))
+}
+
+/// Human readable structural differences between two files: tensors only in
+/// one of them, then tensors whose dtype or shape changed, sorted by name.
+/// Payloads, offsets and `__metadata__` are ignored.
+pub fn diff_headers(a: &[u8], b: &[u8]) -> Result<Vec<String>, CxxError> {
+    let (_, a) = partial_header(a)?;
+    let (_, b) = partial_header(b)?;
+    let a_names: BTreeSet<_> = a.tensors().into_keys().collect();
+    let b_names: BTreeSet<_> = b.tensors().into_keys().collect();
+
+    let mut diffs: Vec<String> = a_names
+        .difference(&b_names)
+        .map(|name| format!("only in a: {name}"))
+        .collect();
+    diffs.extend(
+        b_names
+            .difference(&a_names)
+            .map(|name| format!("only in b: {name}")),
+    );
+    for name in a_names.intersection(&b_names) {
+        let (a_info, b_info) = (a.info(name).unwrap(), b.info(name).unwrap());
+        if a_info.dtype != b_info.dtype {
+            diffs.push(format!(
+                "{name}: dtype {} -> {}",
+                a_info.dtype, b_info.dtype
+            ));
+        }
+        if a_info.shape != b_info.shape {
+            diffs.push(format!(
+                "{name}: shape {:?} -> {:?}",
+                a_info.shape, b_info.shape
+            ));
+        }
+    }
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CowTensor;
+    use std::borrow::Cow;
+
+    fn model(names: &[&str]) -> Vec<u8> {
+        let data = [0u8, 1, 2, 3];
+        let tensors: Vec<_> = names
+            .iter()
+            .map(|&name| {
+                let tensor = CowTensor {
+                    dtype: RDtype::U8,
+                    shape: vec![4],
+                    data: Cow::Owned(data.to_vec()),
+                };
+                (name, tensor)
+            })
+            .collect();
+        safetensors::serialize(tensors, None).unwrap()
+    }
+
+    fn names(buffer: &[u8]) -> Vec<String> {
+        let mut names: Vec<_> = SafeTensors::deserialize(buffer)
+            .unwrap()
+            .names()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn sniff() {
+        let buffer = model(&["a"]);
+        assert!(is_safetensors(&buffer));
+        let n = u64::from_le_bytes(buffer[..N_LEN].try_into().unwrap()) as usize;
+        assert!(is_safetensors(&buffer[..N_LEN + n]));
+        assert!(!is_safetensors(&buffer[..N_LEN + n - 1]));
+        assert!(!is_safetensors(&buffer[..4]));
+        assert!(!is_safetensors(b"PK\x03\x04 not a safetensors file"));
+
+        let mut bad = buffer.clone();
+        bad[N_LEN] = b'[';
+        assert!(!is_safetensors(&bad));
+        let mut huge = buffer;
+        huge[..N_LEN].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(!is_safetensors(&huge));
+    }
+
+    #[test]
+    fn header_diff() {
+        let tensor = |dtype, shape: Vec<usize>| CowTensor {
+            dtype,
+            shape,
+            data: Cow::Owned(vec![0u8; 8]),
+        };
+        let a = safetensors::serialize(
+            vec![
+                ("kept", tensor(RDtype::U8, vec![8])),
+                ("recast", tensor(RDtype::F32, vec![2])),
+                ("reshaped", tensor(RDtype::U8, vec![2, 4])),
+                ("removed", tensor(RDtype::U8, vec![8])),
+            ],
+            None,
+        )
+        .unwrap();
+        let b = safetensors::serialize(
+            vec![
+                ("kept", tensor(RDtype::U8, vec![8])),
+                ("recast", tensor(RDtype::I32, vec![2])),
+                ("reshaped", tensor(RDtype::U8, vec![4, 2])),
+                ("added", tensor(RDtype::U8, vec![8])),
+            ],
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            diff_headers(&a, &b).unwrap(),
+            vec![
+                "only in a: removed",
+                "only in b: added",
+                "recast: dtype F32 -> I32",
+                "reshaped: shape [2, 4] -> [4, 2]",
+            ]
+        );
+        assert!(diff_headers(&a, &a).unwrap().is_empty());
+        assert!(diff_headers(&a, &b[..4]).is_err());
+    }
+
+    #[test]
+    fn index() {
+        let buffer = model(&["a", "b"]);
+        let n = u64::from_le_bytes(buffer[..N_LEN].try_into().unwrap()) as usize;
+        let header_only = &buffer[..N_LEN + n];
+
+        let relative = build_index(header_only, false).unwrap();
+        let names: Vec<_> = relative.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!((relative[1].offset, relative[1].data_len), (4, 4));
+        assert_eq!(
+            (relative[1].dtype, relative[1].shape.clone()),
+            (Dtype::U8, vec![4])
+        );
+
+        let absolute = build_index(header_only, true).unwrap();
+        for (abs, rel) in absolute.iter().zip(&relative) {
+            assert_eq!(abs.offset, N_LEN + n + rel.offset);
+            assert_eq!(&buffer[abs.offset..abs.offset + abs.data_len], [0, 1, 2, 3]);
+        }
+        assert!(build_index(&header_only[..N_LEN + n - 1], true).is_err());
+    }
+
+    #[test]
+    fn removed_tensors() {
+        let tensor = |value: u8, len| CowTensor {
+            dtype: RDtype::U8,
+            shape: vec![len],
+            data: Cow::Owned(vec![value; len]),
+        };
+        let tensors = vec![
+            ("a", tensor(1, 2)),
+            ("b", tensor(2, 3)),
+            ("c", tensor(3, 1)),
+        ];
+        let info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        let buffer = safetensors::serialize(tensors, Some(info)).unwrap();
+
+        let out = remove_tensors(&buffer, vec!["b".to_string()]).unwrap();
+        let safetensors = SafeTensors::deserialize(&out).unwrap();
+        assert_eq!(names(&out), ["a", "c"]);
+        assert_eq!(safetensors.tensor("a").unwrap().data(), [1, 1]);
+        assert_eq!(safetensors.tensor("c").unwrap().data(), [3]);
+        let (n, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        assert_eq!(out.len() - N_LEN - n, 3);
+        assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+
+        let all = ["a", "b", "c"].map(str::to_string).to_vec();
+        assert!(names(&remove_tensors(&buffer, all).unwrap()).is_empty());
+        assert!(matches!(
+            remove_tensors(&buffer, vec!["d".to_string()]),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(name))) if name == "d"
+        ));
+    }
+
+    #[test]
+    fn rebuilt_header() {
+        let spec = |name: &str, dtype, shape: Vec<usize>| TensorSpec {
+            name: name.to_string(),
+            dtype,
+            shape,
+        };
+        let tensors = || {
+            vec![
+                spec("weight", Dtype::F32, vec![2, 2]),
+                spec("packed", Dtype::F4, vec![3]),
+            ]
+        };
+        let data_info = vec![PairStrStr {
+            key: "format".to_string(),
+            value: "pt".to_string(),
+        }];
+        let payload = [0xabu8; 19];
+
+        let mut buffer = rebuild_header(tensors(), data_info, payload.len()).unwrap();
+        assert_eq!(buffer.len() % N_LEN, 0);
+        buffer.extend(payload);
+        let (_, metadata) = SafeTensors::read_metadata(&buffer).unwrap();
+        assert_eq!(metadata.info("packed").unwrap().data_offsets, (16, 19));
+        assert_eq!(metadata.info("packed").unwrap().shape, vec![6]);
+        assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+        assert!(SafeTensors::deserialize(&buffer).is_ok());
+
+        assert!(matches!(
+            rebuild_header(tensors(), Vec::new(), 20),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        let duplicated = vec![spec("a", Dtype::U8, vec![1]), spec("a", Dtype::U8, vec![1])];
+        assert!(matches!(
+            rebuild_header(duplicated, Vec::new(), 2),
+            Err(CxxError::DuplicateName(_))
+        ));
+    }
+
+    #[test]
+    fn explicit_offsets() {
+        let location = |name: &str, dtype, shape: Vec<usize>, offset, data_len| TensorLocation {
+            name: name.to_string(),
+            shape,
+            dtype,
+            offset,
+            data_len,
+        };
+        let payload = [1u8, 2, 3, 4, 5, 6];
+        // Listed out of order, and with "b" ahead of "a" in the payload,
+        // unlike what `serialize` would pick.
+        let entries = || {
+            vec![
+                location("a", Dtype::U8, vec![2], 4, 2),
+                location("b", Dtype::U16, vec![2], 0, 4),
+            ]
+        };
+
+        let buffer = serialize_with_offsets(entries(), &payload, Vec::new()).unwrap();
+        let safetensors = SafeTensors::deserialize(&buffer).unwrap();
+        assert_eq!(safetensors.tensor("a").unwrap().data(), [5, 6]);
+        assert_eq!(safetensors.tensor("b").unwrap().data(), [1, 2, 3, 4]);
+        assert_eq!(build_index(&buffer, false).unwrap(), {
+            let mut expected = entries();
+            expected.reverse();
+            expected
+        });
+
+        assert!(matches!(
+            serialize_with_offsets(entries(), &payload[..5], Vec::new()),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        let gap = vec![
+            location("a", Dtype::U8, vec![2], 4, 2),
+            location("b", Dtype::U8, vec![3], 0, 3),
+        ];
+        assert!(matches!(
+            serialize_with_offsets(gap, &payload, Vec::new()),
+            Err(CxxError::SafeTensorError(SafeTensorError::InvalidOffset(_)))
+        ));
+        let wrong_size = vec![location("a", Dtype::U16, vec![2], 0, 6)];
+        assert!(serialize_with_offsets(wrong_size, &payload, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("layers.?.attn.*", "layers.0.attn.q.weight"));
+        assert!(!glob_match("layers.?.attn.*", "layers.10.attn.q.weight"));
+        assert!(glob_match("*.attention.*", "model.layers.3.attention.wq"));
+        assert!(glob_match("*weight", "a.weight.weight"));
+        assert!(!glob_match("*.bias", "a.weight"));
+        assert!(glob_match("ünï*?", "ünïcode"));
+
+        let buffer = model(&["a.attention.q", "a.mlp.up", "b.attention.k"]);
+        let mut found = find(&buffer, "*.attention.*").unwrap();
+        found.sort();
+        assert_eq!(found, vec!["a.attention.q", "b.attention.k"]);
+        assert!(find(&buffer, "missing*").unwrap().is_empty());
+    }
+
+    #[test]
+    fn summary() {
+        let tensor = |dtype, len| CowTensor {
+            dtype,
+            shape: vec![len],
+            data: Cow::Owned(vec![0u8; len * dtype.bitsize() / 8]),
+        };
+        let info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        let buffer = safetensors::serialize(
+            vec![
+                ("a", tensor(RDtype::F32, 4)),
+                ("b", tensor(RDtype::BF16, 2)),
+                ("c", tensor(RDtype::F32, 1)),
+            ],
+            Some(info),
+        )
+        .unwrap();
+
+        let summary = summarize(&buffer).unwrap();
+        assert_eq!(summary.num_tensors, 3);
+        assert_eq!(summary.total_bytes, 24);
+        assert!(summary.has_metadata);
+        assert_eq!(
+            summary.dtype_counts,
+            vec![
+                PairDtypeUsize {
+                    key: Dtype::BF16,
+                    value: 1
+                },
+                PairDtypeUsize {
+                    key: Dtype::F32,
+                    value: 2
+                },
+            ]
+        );
+
+        let summary = summarize(&model(&[])).unwrap();
+        assert_eq!((summary.num_tensors, summary.total_bytes), (0, 0));
+        assert!(summary.dtype_counts.is_empty());
+        assert!(!summary.has_metadata);
+    }
+
+    #[test]
+    fn layout() {
+        let buffer = model(&["a", "b"]);
+        let layout = layout_info(&buffer).unwrap();
+        assert_eq!(layout.payload_bytes, 8);
+        assert_eq!(layout.total_bytes, buffer.len());
+        assert_eq!(layout.header_bytes + layout.payload_bytes, buffer.len());
+        assert_eq!(layout.header_bytes % N_LEN, 0);
+        assert!(layout_info(&buffer[..buffer.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn parameters() {
+        let tensor = |dtype: RDtype, shape: Vec<usize>| CowTensor {
+            dtype,
+            data: Cow::Owned(vec![
+                0u8;
+                shape.iter().product::<usize>() * dtype.bitsize() / 8
+            ]),
+            shape,
+        };
+        let buffer = safetensors::serialize(
+            vec![
+                ("weight", tensor(RDtype::BF16, vec![4, 3])),
+                ("packed", tensor(RDtype::F4, vec![6])),
+                ("position_ids", tensor(RDtype::I64, vec![1, 5])),
+                ("scale", tensor(RDtype::F32, vec![])),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(count_parameters(&buffer, false).unwrap(), 24);
+        assert_eq!(count_parameters(&buffer, true).unwrap(), 19);
+        assert_eq!(count_parameters(&model(&[]), true).unwrap(), 0);
+        assert!(count_parameters(&buffer[..4], false).is_err());
+    }
+
+    #[test]
+    fn required_dtype() {
+        let tensor = |dtype: RDtype| CowTensor {
+            dtype,
+            shape: vec![2],
+            data: Cow::Owned(vec![0u8; dtype.bitsize() / 4]),
+        };
+        let buffer = safetensors::serialize(
+            vec![
+                ("a", tensor(RDtype::F32)),
+                ("b", tensor(RDtype::BF16)),
+                ("c", tensor(RDtype::I64)),
+            ],
+            None,
+        )
+        .unwrap();
+
+        let err = require_dtype(&buffer, Dtype::F32).unwrap_err();
+        assert!(matches!(&err, CxxError::UnexpectedDtypes(RDtype::F32, _)));
+        assert_eq!(
+            err.to_string(),
+            "expected only F32 tensors, found c (I64), b (BF16)"
+        );
+        assert!(require_dtype(&model(&["a", "b"]), Dtype::U8).is_ok());
+        assert!(require_dtype(&model(&[]), Dtype::F32).is_ok());
+    }
+
+    #[test]
+    fn expected_names() {
+        let buffer = model(&["embed", "layers.0.mlp", "extra"]);
+        let expected = || {
+            ["layers.1.mlp", "embed", "layers.0.mlp", "head"]
+                .map(str::to_string)
+                .to_vec()
+        };
+        assert_eq!(
+            find_missing(&buffer, expected()).unwrap(),
+            vec!["layers.1.mlp", "head"]
+        );
+        assert_eq!(find_unexpected(&buffer, expected()).unwrap(), vec!["extra"]);
+        assert!(find_missing(&buffer, Vec::new()).unwrap().is_empty());
+        assert_eq!(find_unexpected(&buffer, Vec::new()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn shape_check() {
+        let buffer = model(&["a"]);
+        assert!(check_shape(&buffer, "a", vec![4]).unwrap());
+        assert!(!check_shape(&buffer, "a", vec![2, 2]).unwrap());
+        assert!(!check_shape(&buffer, "a", vec![]).unwrap());
+        assert!(matches!(
+            check_shape(&buffer, "b", vec![4]),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn repair_last_offset() {
+        let header_with = |end: usize| {
+            let header = format!(
+                r#"{{"__metadata__":{{"format":"pt"}},"a":{{"dtype":"U8","shape":[2],"data_offsets":[0,2]}},"b":{{"dtype":"I16","shape":[2],"data_offsets":[2,{end}]}}}}"#
+            );
+            let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+            bytes.extend(header.as_bytes());
+            bytes.extend([1, 2, 3, 0, 4, 0]);
+            bytes
+        };
+
+        let valid = header_with(6);
+        assert_eq!(try_repair(&valid).unwrap(), valid);
+
+        let repaired = try_repair(&header_with(4)).unwrap();
+        let loaded = SafeTensors::deserialize(&repaired).unwrap();
+        assert_eq!(loaded.tensor("b").unwrap().data(), &[3, 0, 4, 0]);
+        assert_eq!(loaded.tensor("a").unwrap().data(), &[1, 2]);
+        let (_, metadata) = SafeTensors::read_metadata(&repaired).unwrap();
+        assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+
+        // Bytes beyond what `b` can hold can't be attributed to it.
+        let mut trailing = header_with(6);
+        trailing.extend([0, 0]);
+        assert!(matches!(
+            try_repair(&trailing),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        let mut short = header_with(4);
+        short.extend([0, 0]);
+        assert!(try_repair(&short).is_err());
+    }
+
+    #[test]
+    fn normalized_names() {
+        let buffer = model(&["dense/kernel", "layers.0.attn/q"]);
+        let normalized = normalize_names(&buffer, ".").unwrap();
+        assert_eq!(names(&normalized), vec!["dense.kernel", "layers.0.attn.q"]);
+        let normalized = normalize_names(&normalized, "::").unwrap();
+        assert_eq!(
+            names(&normalized),
+            vec!["dense::kernel", "layers::0::attn::q"]
+        );
+
+        let clashing = model(&["a.b", "a/b"]);
+        assert!(matches!(
+            normalize_names(&clashing, "."),
+            Err(CxxError::DuplicateName(name)) if name == "a.b"
+        ));
+    }
+
+    #[test]
+    fn padding() {
+        let packed = model(&["a", "b"]);
+        let report = padding_report(&packed).unwrap();
+        assert!(report.iter().all(|entry| entry.value == 0));
+        assert_eq!(report.len(), 2);
+
+        let header = concat!(
+            r#"{"__metadata__":{"format":"pt"},"#,
+            r#""b":{"dtype":"F32","shape":[1],"data_offsets":[8,12]},"#,
+            r#""a":{"dtype":"U8","shape":[3],"data_offsets":[0,3]}}"#,
+        );
+        let mut padded = (header.len() as u64).to_le_bytes().to_vec();
+        padded.extend(header.as_bytes());
+        padded.extend([0; 12]);
+        let report = padding_report(&padded).unwrap();
+        let report: Vec<_> = report.iter().map(|e| (e.key.as_str(), e.value)).collect();
+        assert_eq!(report, vec![("a", 0), ("b", 5)]);
+
+        let header = concat!(
+            r#"{"a":{"dtype":"U8","shape":[4],"data_offsets":[0,4]},"#,
+            r#""b":{"dtype":"U8","shape":[4],"data_offsets":[2,6]}}"#,
+        );
+        let mut overlapping = (header.len() as u64).to_le_bytes().to_vec();
+        overlapping.extend(header.as_bytes());
+        assert!(matches!(
+            padding_report(&overlapping),
+            Err(CxxError::SafeTensorError(SafeTensorError::InvalidOffset(name))) if name == "b"
+        ));
+    }
+
+    #[test]
+    fn repacked() {
+        let weight = CowTensor {
+            dtype: RDtype::F32,
+            shape: vec![3],
+            data: Cow::Owned((0..12).collect()),
+        };
+        let flag = CowTensor {
+            dtype: RDtype::BOOL,
+            shape: vec![5],
+            data: Cow::Owned(vec![1; 5]),
+        };
+        let data_info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        let packed =
+            safetensors::serialize([("weight", weight), ("flag", flag)], Some(data_info)).unwrap();
+
+        let aligned = repack(&packed, 64).unwrap();
+        let (header, payload) = split_header(&aligned).unwrap();
+        assert_eq!((N_LEN + header.len()) % 64, 0);
+        assert_eq!(payload, split_header(&packed).unwrap().1);
+        let original = SafeTensors::deserialize(&packed).unwrap();
+        let repacked = SafeTensors::deserialize(&aligned).unwrap();
+        for (name, tensor) in original.tensors() {
+            assert_eq!(repacked.tensor(&name).unwrap(), tensor);
+        }
+        let (_, before) = SafeTensors::read_metadata(&packed).unwrap();
+        let (_, after) = SafeTensors::read_metadata(&aligned).unwrap();
+        assert_eq!(after.offset_keys(), before.offset_keys());
+        assert_eq!(after.metadata(), before.metadata());
+
+        assert_eq!(repack(&aligned, 0).unwrap(), packed);
+        assert_eq!(repack(&packed, 0).unwrap(), packed);
+
+        // Gaps left by other writers are closed up.
+        let header = concat!(
+            r#"{"b":{"dtype":"F32","shape":[1],"data_offsets":[8,12]},"#,
+            r#""a":{"dtype":"U8","shape":[3],"data_offsets":[0,3]}}"#,
+        );
+        let mut gapped = (header.len() as u64).to_le_bytes().to_vec();
+        gapped.extend(header.as_bytes());
+        gapped.extend([1, 2, 3, 0, 0, 0, 0, 0, 4, 5, 6, 7]);
+        let closed = repack(&gapped, 0).unwrap();
+        let report = padding_report(&closed).unwrap();
+        assert!(report.iter().all(|entry| entry.value == 0));
+        assert_eq!(
+            split_header(&closed).unwrap().1,
+            [1, 2, 3, 4, 5, 6, 7].as_slice()
+        );
+        assert!(matches!(
+            repack(&packed, 48),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::TensorInvalidInfo
+            ))
+        ));
+    }
+
+    #[test]
+    fn merged_metadata() {
+        let pairs = |items: &[(&str, &str)]| {
+            items
+                .iter()
+                .map(|&(key, value)| PairStrStr {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let flat = |items: Vec<PairStrStr>| {
+            items
+                .into_iter()
+                .map(|item| format!("{}={}", item.key, item.value))
+                .collect::<Vec<_>>()
+        };
+        let a = pairs(&[("format", "pt"), ("author", "a")]);
+        let b = pairs(&[("author", "b"), ("license", "mit")]);
+
+        assert_eq!(
+            flat(merge_metadata(a.clone(), b.clone(), true)),
+            vec!["format=pt", "author=a", "license=mit"]
+        );
+        assert_eq!(
+            flat(merge_metadata(a, b, false)),
+            vec!["format=pt", "author=b", "license=mit"]
+        );
+    }
+
+    #[test]
+    fn prefixes() {
+        let buffer = model(&["module.a", "module.b", "c"]);
+        let stripped = strip_prefix(&buffer, "module.").unwrap();
+        assert_eq!(names(&stripped), vec!["a", "b", "c"]);
+        let n = u64::from_le_bytes(stripped[..N_LEN].try_into().unwrap());
+        assert_eq!(n % N_LEN as u64, 0);
+
+        let added = add_prefix(&stripped, "model.").unwrap();
+        assert_eq!(names(&added), vec!["model.a", "model.b", "model.c"]);
+        let tensors = SafeTensors::deserialize(&added).unwrap();
+        assert_eq!(tensors.tensor("model.b").unwrap().data(), &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rename_collision() {
+        let buffer = model(&["module.a", "a"]);
+        assert!(matches!(
+            strip_prefix(&buffer, "module."),
+            Err(CxxError::DuplicateName(name)) if name == "a"
+        ));
+
+        let buffer = model(&["module.", "module.a"]);
+        assert!(matches!(
+            strip_prefix(&buffer, "module."),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::TensorInvalidInfo
+            ))
+        ));
+        let buffer = model(&["metadata__"]);
+        assert!(matches!(
+            add_prefix(&buffer, "__"),
+            Err(CxxError::ReservedName(name)) if name == "__metadata__"
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+//! Loading from HTTP(S) URLs, only available with the `http` feature.
+//! The functions are always exposed to C++ so the generated header doesn't
+//! depend on the features, and fail with `FeatureDisabled` otherwise.
+use crate::error::CxxError;
+use crate::ffi::{OwnedTensorView, PairStrOwnedTensorView};
+
+/// Download the whole file at `url` and deserialize it.
+#[cfg(feature = "http")]
+pub fn load_url(url: &str) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+    use std::io::Read;
+
+    let response = ureq::get(url).call().map_err(Box::new)?;
+    let mut bytes = Vec::new();
+    response.into_reader().read_to_end(&mut bytes)?;
+    Ok(crate::deserialize_owned(&bytes)?)
+}
+
+/// Fetch a single tensor from `url` with range requests: one for the length
+/// prefix, one for the header and one for the tensor's own bytes.
+#[cfg(feature = "http")]
+pub fn load_url_tensor(url: &str, name: &str) -> Result<OwnedTensorView, CxxError> {
+    use crate::{bridge_shape, header_len, parse_header, N_LEN};
+    use safetensors::SafeTensorError;
+
+    let prefix = fetch_range(url, 0, N_LEN)?;
+    let n = header_len(
+        prefix
+            .try_into()
+            .expect("fetch_range returns exactly N_LEN bytes"),
+    )?;
+    let metadata = parse_header(&fetch_range(url, N_LEN, n)?)?;
+    let info = metadata
+        .info(name)
+        .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+
+    let (start, end) = info.data_offsets;
+    let data = fetch_range(url, N_LEN + n + start, end - start)?;
+    Ok(OwnedTensorView {
+        shape: bridge_shape(info.dtype, &info.shape),
+        dtype: info.dtype.try_into()?,
+        data,
+    })
+}
+
+/// Download `len` bytes starting at `start`. Servers ignoring the `Range`
+/// header are tolerated by skipping to the requested bytes of the full body.
+#[cfg(feature = "http")]
+fn fetch_range(url: &str, start: usize, len: usize) -> Result<Vec<u8>, CxxError> {
+    use std::io::{ErrorKind, Read};
+
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let range = format!("bytes={}-{}", start, start + len - 1);
+    let response = ureq::get(url)
+        .set("Range", &range)
+        .call()
+        .map_err(Box::new)?;
+    let skip = if response.status() == 206 { 0 } else { start };
+
+    let mut reader = response.into_reader().take((skip + len) as u64);
+    std::io::copy(&mut reader.by_ref().take(skip as u64), &mut std::io::sink())?;
+    let mut data = Vec::with_capacity(len);
+    reader.read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+    }
+    Ok(data)
+}
+
+#[cfg(not(feature = "http"))]
+pub fn load_url(_url: &str) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+    Err(CxxError::FeatureDisabled("http"))
+}
+
+#[cfg(not(feature = "http"))]
+pub fn load_url_tensor(_url: &str, _name: &str) -> Result<OwnedTensorView, CxxError> {
+    Err(CxxError::FeatureDisabled("http"))
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+    use crate::ffi::Dtype;
+    use crate::CowTensor;
+    use std::borrow::Cow;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Serve `body` over HTTP, honouring single `Range` requests, and record
+    /// the ranges asked for.
+    fn serve(body: Vec<u8>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/model.safetensors",
+            listener.local_addr().unwrap()
+        );
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Range: bytes=") {
+                        range = Some(value.to_string());
+                    }
+                }
+                let (status, content) = match &range {
+                    Some(range) => {
+                        seen.lock().unwrap().push(range.clone());
+                        let (start, end) = range.split_once('-').unwrap();
+                        let (start, end): (usize, usize) =
+                            (start.parse().unwrap(), end.parse().unwrap());
+                        ("206 Partial Content", &body[start..=end])
+                    }
+                    None => ("200 OK", &body[..]),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    content.len()
+                )
+                .unwrap();
+                stream.write_all(content).unwrap();
+            }
+        });
+        (url, ranges)
+    }
+
+    fn model() -> Vec<u8> {
+        let small = [1u8, 2, 3, 4];
+        let large = [7u8; 64];
+        let tensors = vec![
+            (
+                "small",
+                CowTensor {
+                    dtype: safetensors::Dtype::U8,
+                    shape: vec![4],
+                    data: Cow::Borrowed(&small),
+                },
+            ),
+            (
+                "large",
+                CowTensor {
+                    dtype: safetensors::Dtype::U8,
+                    shape: vec![8, 8],
+                    data: Cow::Borrowed(&large),
+                },
+            ),
+        ];
+        safetensors::serialize(tensors, None).unwrap()
+    }
+
+    #[test]
+    fn load_whole_url() {
+        let (url, _) = serve(model());
+        let mut tensors = load_url(&url).unwrap();
+        tensors.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(tensors.len(), 2);
+        assert_eq!(tensors[1].key, "small");
+        assert_eq!(tensors[1].value.data, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn load_single_tensor() {
+        let body = model();
+        let n = body.len() - 8 - 68;
+        let (url, ranges) = serve(body);
+
+        let tensor = load_url_tensor(&url, "small").unwrap();
+        assert_eq!(tensor.shape, vec![4]);
+        assert_eq!(tensor.dtype, Dtype::U8);
+        assert_eq!(tensor.data, vec![1, 2, 3, 4]);
+
+        // Only the prefix, the header and the 4 bytes of `small` were fetched.
+        let tensor_start = 8 + n + 64;
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            vec![
+                "0-7".to_string(),
+                format!("8-{}", 8 + n - 1),
+                format!("{}-{}", tensor_start, tensor_start + 3),
+            ]
+        );
+
+        assert!(load_url_tensor(&url, "missing").is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Operations rearranging the bytes of a tensor without decoding its values.
+use crate::error::CxxError;
+use crate::ffi::{Dtype, OwnedTensorView, TensorView};
+use crate::{bridge_shape, check_view, normalize_shape, tensor_view_checked};
+use safetensors::Dtype as RDtype;
+use safetensors::{SafeTensorError, SafeTensors};
+
+/// Row-major strides in elements of a tensor of the given shape.
+fn element_strides(shape: &[usize]) -> Result<Vec<usize>, SafeTensorError> {
+    let mut strides = vec![1usize; shape.len()];
+    for i in (0..shape.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1]
+            .checked_mul(shape[i + 1])
+            .ok_or(SafeTensorError::ValidationOverflow)?;
+    }
+    Ok(strides)
+}
+
+/// Row-major strides of `view` in elements, as seen from C++: an F4 element
+/// there is a pair of F4 values.
+pub fn strides(view: &TensorView) -> Result<Vec<usize>, CxxError> {
+    Ok(element_strides(&view.shape)?)
+}
+
+/// Size in bytes of an element as seen from C++, where F4 values come in
+/// pairs. Fails for F6 dtypes whose elements don't start on a byte boundary.
+fn element_bytes(dtype: RDtype) -> Result<usize, SafeTensorError> {
+    let element_bits = if dtype == RDtype::F4 {
+        8
+    } else {
+        dtype.bitsize()
+    };
+    if !element_bits.is_multiple_of(8) {
+        return Err(SafeTensorError::MisalignedSlice);
+    }
+    Ok(element_bits / 8)
+}
+
+/// Same as `strides`, in bytes. Fails for F6 dtypes whose elements don't
+/// start on a byte boundary.
+pub fn byte_strides(view: &TensorView) -> Result<Vec<usize>, CxxError> {
+    let element_bytes = element_bytes(view.dtype.try_into()?)?;
+    element_strides(&view.shape)?
+        .into_iter()
+        .map(|stride| {
+            stride
+                .checked_mul(element_bytes)
+                .ok_or(SafeTensorError::ValidationOverflow.into())
+        })
+        .collect()
+}
+
+/// Contiguous row-major copy of a tensor whose elements sit in `data` at
+/// the given `strides`, in elements, e.g. a channels-last buffer or a
+/// transposed view. Strides may repeat elements, such as 0 for a broadcast
+/// axis, but every element they reach must lie within `data`.
+pub fn from_strided(
+    dtype: Dtype,
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+    data: &[u8],
+) -> Result<OwnedTensorView, CxxError> {
+    if strides.len() != shape.len() {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+    let element_bytes = element_bytes(dtype.try_into()?)?;
+    let count = shape
+        .iter()
+        .try_fold(1usize, |acc, &dim| acc.checked_mul(dim))
+        .ok_or(SafeTensorError::ValidationOverflow)?;
+    if count == 0 {
+        return tensor_view_checked(dtype, shape, Vec::new());
+    }
+    let last = shape
+        .iter()
+        .zip(&strides)
+        .try_fold(0usize, |acc, (&dim, &stride)| {
+            acc.checked_add((dim - 1).checked_mul(stride)?)
+        })
+        .ok_or(SafeTensorError::ValidationOverflow)?;
+    if last >= data.len() / element_bytes {
+        return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+    }
+
+    let mut out = Vec::with_capacity(count * element_bytes);
+    let mut index = vec![0usize; shape.len()];
+    let mut offset = 0;
+    for _ in 0..count {
+        let start = offset * element_bytes;
+        out.extend_from_slice(&data[start..start + element_bytes]);
+        // Odometer increment of the multi-index, innermost axis first. Steps
+        // past the end of an axis are undone right away, so wrapping only
+        // matters for unused strides, e.g. a huge one on an axis of size 1.
+        for axis in (0..shape.len()).rev() {
+            index[axis] += 1;
+            offset = offset.wrapping_add(strides[axis]);
+            if index[axis] < shape[axis] {
+                break;
+            }
+            offset = offset.wrapping_sub(index[axis].wrapping_mul(strides[axis]));
+            index[axis] = 0;
+        }
+    }
+    tensor_view_checked(dtype, shape, out)
+}
+
+/// Tensor `name` of `buffer` with its data copied out in column-major order,
+/// the layout Eigen's default `Map` expects. The shape is unchanged, only
+/// the bytes are transposed, the first axis varying fastest. Vectors and
+/// scalars come out as stored.
+pub fn get_tensor_colmajor(buffer: &[u8], name: &str) -> Result<OwnedTensorView, CxxError> {
+    let tensors = SafeTensors::deserialize(buffer)?;
+    let tensor = tensors.tensor(name)?;
+    let dtype = tensor.dtype();
+    let shape = bridge_shape(dtype, tensor.shape());
+
+    // Reading the row-major data with both axes and strides reversed visits
+    // elements in column-major order.
+    let mut strides = element_strides(&shape)?;
+    strides.reverse();
+    let reversed = shape.iter().rev().copied().collect();
+    let mut transposed = from_strided(dtype.try_into()?, reversed, strides, tensor.data())?;
+    transposed.shape = shape;
+    Ok(transposed)
+}
+
+/// Split `view` into `parts` chunks of equal size along `axis`, each owning
+/// a copy of its bytes. `shape[axis]` must be a multiple of `parts`.
+pub fn split(
+    view: &TensorView,
+    axis: usize,
+    parts: usize,
+) -> Result<Vec<OwnedTensorView>, CxxError> {
+    let dtype: RDtype = view.dtype.try_into()?;
+    let shape = normalize_shape(dtype, &view.shape);
+    check_view(dtype, &shape, view.data.len())?;
+    let Some(&dim) = shape.get(axis) else {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    };
+    if parts == 0 || !dim.is_multiple_of(parts) {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+
+    // The tensor is `outer` blocks of `dim` slices, each slice `inner_bits`
+    // long; a chunk takes `dim / parts` consecutive slices of every block.
+    let outer: usize = shape[..axis].iter().product();
+    let inner_bits = shape[axis + 1..].iter().product::<usize>() * dtype.bitsize();
+    let chunk_bits = dim / parts * inner_bits;
+    if !chunk_bits.is_multiple_of(8) {
+        return Err(SafeTensorError::MisalignedSlice.into());
+    }
+    let chunk_bytes = chunk_bits / 8;
+
+    let mut chunk_shape = shape.clone();
+    chunk_shape[axis] = dim / parts;
+    let chunks = (0..parts)
+        .map(|part| {
+            let mut data = Vec::with_capacity(outer * chunk_bytes);
+            for block in 0..outer {
+                let start = (block * parts + part) * chunk_bytes;
+                data.extend_from_slice(&view.data[start..start + chunk_bytes]);
+            }
+            OwnedTensorView {
+                shape: bridge_shape(dtype, &chunk_shape),
+                dtype: view.dtype,
+                data,
+            }
+        })
+        .collect();
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_major_strides() {
+        let view = TensorView {
+            shape: vec![2, 3, 4],
+            dtype: Dtype::F32,
+            data: &[],
+            data_len: 0,
+        };
+        assert_eq!(strides(&view).unwrap(), vec![12, 4, 1]);
+        assert_eq!(byte_strides(&view).unwrap(), vec![48, 16, 4]);
+
+        let scalar = TensorView {
+            shape: vec![],
+            ..view.clone()
+        };
+        assert!(strides(&scalar).unwrap().is_empty());
+
+        let packed = TensorView {
+            shape: vec![3, 2],
+            dtype: Dtype::F4,
+            ..view.clone()
+        };
+        assert_eq!(byte_strides(&packed).unwrap(), vec![2, 1]);
+        let f6 = TensorView {
+            dtype: Dtype::F6_E2M3,
+            ..view.clone()
+        };
+        assert!(byte_strides(&f6).is_err());
+        let huge = TensorView {
+            shape: vec![2, usize::MAX, 2],
+            ..view
+        };
+        assert!(strides(&huge).is_err());
+    }
+
+    #[test]
+    fn split_axes() {
+        let data: Vec<u8> = (0..12).collect();
+        let view = TensorView {
+            shape: vec![2, 6],
+            dtype: Dtype::U8,
+            data: &data,
+            data_len: data.len(),
+        };
+
+        let rows = split(&view, 0, 2).unwrap();
+        assert_eq!(rows[1].shape, vec![1, 6]);
+        assert_eq!(rows[1].data, vec![6, 7, 8, 9, 10, 11]);
+
+        let cols = split(&view, 1, 3).unwrap();
+        assert_eq!(cols.len(), 3);
+        assert_eq!(cols[0].shape, vec![2, 2]);
+        assert_eq!(cols[0].data, vec![0, 1, 6, 7]);
+        assert_eq!(cols[2].data, vec![4, 5, 10, 11]);
+
+        assert!(split(&view, 1, 4).is_err());
+        assert!(split(&view, 1, 0).is_err());
+        assert!(split(&view, 2, 1).is_err());
+
+        let wide: Vec<u8> = (0..8u16).flat_map(|v| v.to_le_bytes()).collect();
+        let wide = TensorView {
+            shape: vec![2, 4],
+            dtype: Dtype::U16,
+            data: &wide,
+            data_len: wide.len(),
+        };
+        let halves = split(&wide, 1, 2).unwrap();
+        assert_eq!(halves[1].data, vec![2, 0, 3, 0, 6, 0, 7, 0]);
+    }
+
+    #[test]
+    fn strided_gather() {
+        // A 2x3 U16 tensor stored column-major, i.e. strides [1, 2].
+        let data: Vec<u8> = [0u16, 3, 1, 4, 2, 5]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let tensor = from_strided(Dtype::U16, vec![2, 3], vec![1, 2], &data).unwrap();
+        assert_eq!(tensor.shape, vec![2, 3]);
+        let expected: Vec<u8> = (0..6u16).flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(tensor.data, expected);
+
+        // Broadcasting a row with a zero stride.
+        let row = [1u8, 2];
+        let tensor = from_strided(Dtype::U8, vec![3, 2], vec![0, 1], &row).unwrap();
+        assert_eq!(tensor.data, vec![1, 2, 1, 2, 1, 2]);
+        let empty = from_strided(Dtype::U8, vec![0, 2], vec![2, 1], &[]).unwrap();
+        assert!(empty.data.is_empty());
+
+        assert!(matches!(
+            from_strided(Dtype::U16, vec![2, 3], vec![1, 3], &data),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        assert!(from_strided(Dtype::U16, vec![2, 3], vec![1], &data).is_err());
+        let column = from_strided(Dtype::U8, vec![3, 1], vec![1, usize::MAX], &row[..1]);
+        assert!(column.is_err());
+        let column = from_strided(Dtype::U8, vec![2, 1], vec![1, usize::MAX], &row).unwrap();
+        assert_eq!(column.data, row);
+        assert!(from_strided(Dtype::U8, vec![2, 2], vec![usize::MAX, 1], &data).is_err());
+        assert!(from_strided(Dtype::F6_E3M2, vec![1], vec![1], &data).is_err());
+    }
+
+    #[test]
+    fn column_major() {
+        let data: Vec<u8> = (0..6u16).flat_map(|v| v.to_le_bytes()).collect();
+        let bias = [1u8, 2, 3];
+        let buffer = crate::serialize(
+            vec![
+                crate::ffi::PairStrTensorView {
+                    key: "weight".to_string(),
+                    value: TensorView {
+                        shape: vec![2, 3],
+                        dtype: Dtype::U16,
+                        data: &data,
+                        data_len: data.len(),
+                    },
+                },
+                crate::ffi::PairStrTensorView {
+                    key: "bias".to_string(),
+                    value: TensorView {
+                        shape: vec![3],
+                        dtype: Dtype::U8,
+                        data: &bias,
+                        data_len: bias.len(),
+                    },
+                },
+            ],
+            Vec::new(),
+        )
+        .unwrap();
+
+        let weight = get_tensor_colmajor(&buffer, "weight").unwrap();
+        assert_eq!(weight.shape, vec![2, 3]);
+        let expected: Vec<u8> = [0u16, 3, 1, 4, 2, 5]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(weight.data, expected);
+        assert_eq!(get_tensor_colmajor(&buffer, "bias").unwrap().data, bias);
+        assert!(matches!(
+            get_tensor_colmajor(&buffer, "missing"),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                _
+            )))
+        ));
+    }
+}
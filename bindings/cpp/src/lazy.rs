@@ -0,0 +1,126 @@
+//! Lazy handle over a file on disk: only the header is read on open, tensor
+//! bytes are fetched when a `LazyTensor` token is resolved.
+use crate::bridge_shape;
+use crate::error::CxxError;
+use crate::ffi::{LazyTensor, OwnedTensorView};
+use crate::file::read_header;
+use crate::N_LEN;
+use safetensors::tensor::Metadata;
+use safetensors::SafeTensorError;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+/// Handle returned by `open_lazy`.
+pub struct LazySafeTensors {
+    file: Mutex<File>,
+    header_len: usize,
+    metadata: Metadata,
+}
+
+/// Open `path` reading only its header. The file stays open until the
+/// handle is dropped.
+pub fn open_lazy(path: &str) -> Result<Box<LazySafeTensors>, CxxError> {
+    let (file, header_len, metadata) = read_header(path)?;
+    Ok(Box::new(LazySafeTensors {
+        file: Mutex::new(file),
+        header_len,
+        metadata,
+    }))
+}
+
+impl LazySafeTensors {
+    /// Tensor names in the order their data is laid out in the file.
+    pub fn names(&self) -> Vec<String> {
+        self.metadata.offset_keys()
+    }
+
+    /// Name, dtype and shape of tensor `name`, along with where its bytes
+    /// live in the file, without reading them.
+    pub fn view_lazy(&self, name: &str) -> Result<LazyTensor, CxxError> {
+        let info = self
+            .metadata
+            .info(name)
+            .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+        let (start, end) = info.data_offsets;
+        Ok(LazyTensor {
+            name: name.to_string(),
+            shape: bridge_shape(info.dtype, &info.shape),
+            dtype: info.dtype.try_into()?,
+            offset: N_LEN + self.header_len + start,
+            data_len: end - start,
+        })
+    }
+
+    /// Read the bytes `lazy` points to. A token is only meaningful for the
+    /// handle that produced it.
+    pub fn resolve(&self, lazy: &LazyTensor) -> Result<OwnedTensorView, CxxError> {
+        let mut data = vec![0u8; lazy.data_len];
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        file.seek(SeekFrom::Start(lazy.offset as u64))?;
+        file.read_exact(&mut data)?;
+        Ok(OwnedTensorView {
+            shape: lazy.shape.clone(),
+            dtype: lazy.dtype,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::Dtype;
+    use crate::CowTensor;
+    use safetensors::Dtype as RDtype;
+    use std::borrow::Cow;
+
+    #[test]
+    fn lazy_resolve() {
+        let weight: Vec<u8> = (0..6u16).flat_map(|v| v.to_le_bytes()).collect();
+        let bias = [1u8, 2, 3];
+        let tensors = vec![
+            (
+                "weight",
+                CowTensor {
+                    dtype: RDtype::U16,
+                    shape: vec![2, 3],
+                    data: Cow::Borrowed(&weight),
+                },
+            ),
+            (
+                "bias",
+                CowTensor {
+                    dtype: RDtype::U8,
+                    shape: vec![3],
+                    data: Cow::Borrowed(&bias),
+                },
+            ),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("lazy_resolve.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &path).unwrap();
+
+        let handle = open_lazy(path.to_str().unwrap()).unwrap();
+        assert_eq!(handle.names(), vec!["weight", "bias"]);
+
+        let bias_token = handle.view_lazy("bias").unwrap();
+        let weight_token = handle.view_lazy("weight").unwrap();
+        assert_eq!(weight_token.shape, vec![2, 3]);
+        assert_eq!(weight_token.dtype, Dtype::U16);
+        assert_eq!(weight_token.data_len, 12);
+
+        assert_eq!(handle.resolve(&bias_token).unwrap().data, bias);
+        let resolved = handle.resolve(&weight_token).unwrap();
+        assert_eq!(resolved.shape, vec![2, 3]);
+        assert_eq!(resolved.data, weight);
+
+        assert!(matches!(
+            handle.view_lazy("missing"),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                _
+            )))
+        ));
+    }
+}
@@ -1,9 +1,69 @@
-use crate::ffi::{PairStrStr, PairStrTensorView, TensorView};
+use crate::ffi::{
+    Dtype, OwnedTensorView, PairStrOwnedTensorView, PairStrStr, PairStrTensorView,
+    SerializeOptions, TensorOrder, TensorView,
+};
+use safetensors::tensor::{Metadata, TensorInfo};
 use safetensors::Dtype as RDtype;
 use safetensors::{SafeTensorError, SafeTensors, View};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+mod cast;
+mod collection;
+mod constructors;
 mod conversion;
+mod error;
+mod file;
+mod header;
+mod http;
+mod layout;
+mod lazy;
+mod mmap;
+mod numeric;
+mod reader;
+mod shard;
+
+use crate::collection::{new_collection, TensorCollection};
+use crate::constructors::{
+    tensor_filled, tensor_from_bool, tensor_from_chunks, tensor_from_cxx_f32, tensor_from_f32,
+    tensor_from_f64, tensor_from_i16, tensor_from_i32, tensor_from_i64, tensor_from_i8,
+    tensor_from_u16, tensor_from_u32, tensor_from_u64, tensor_from_u8,
+};
+use crate::conversion::{
+    dtype_bits, dtype_from_name_lenient, dtype_group, promote_dtype, safetensors_version,
+    supported_dtypes,
+};
+use crate::error::CxxError;
+use crate::file::{
+    compare_files, extract, load_file_byteswap, load_file_full, load_file_guarded,
+    load_file_verified, load_flat, new_serialize_progress, read_header_bytes, read_tensor_rows,
+    serialize_to_file_hashed, serialize_to_file_progress, transcode, SerializeProgress,
+};
+use crate::header::{
+    add_prefix, build_index, check_shape, count_parameters, diff_headers, find, find_missing,
+    find_unexpected, header_tensors, is_safetensors, layout_info, merge_metadata, normalize_names,
+    padding_report, rebuild_header, remove_tensors, repack, require_dtype, serialize_with_offsets,
+    strip_prefix, summarize, try_repair,
+};
+use crate::http::{load_url, load_url_tensor};
+use crate::layout::{byte_strides, from_strided, get_tensor_colmajor, split, strides};
+use crate::lazy::{open_lazy, LazySafeTensors};
+use crate::mmap::{open_file_mut, open_mmap, MmapSafeTensors, MutableSafeTensors};
+use crate::numeric::{
+    allclose, as_f32_slice, as_f64_slice, as_i32_slice, as_i64_slice, dequantize_i8,
+    downcast_in_place, dtype_can_represent, e8m0_to_scale_f32, fp8_to_f32, sanitize_floats,
+    scalar_f64, scalar_i64, stats_f32, to_i64,
+};
+use crate::reader::{new_reader_loader, ReaderLoader};
+use crate::shard::{concat_files_to_file, reshard, verify_sharded, write_index};
+
+const N_LEN: usize = size_of::<u64>();
+/// Same limit as the one enforced by `safetensors` on in-memory buffers.
+const MAX_HEADER_SIZE: usize = 100_000_000;
+/// Header key holding the global metadata, which no tensor can be named after.
+const METADATA_KEY: &str = "__metadata__";
+/// Separates the tensor name from the field in `__metadata__` keys holding
+/// per-tensor notes, e.g. `encoder.weight:quant`.
+const TENSOR_META_SEPARATOR: char = ':';
 
 #[cxx::bridge(namespace = "safetensors")]
 mod ffi {
@@ -55,6 +115,71 @@ mod ffi {
         U64,
     }
 
+    /// Knobs of `serialize_with_options`. The defaults, from
+    /// `default_serialize_options`, give the same output as `serialize`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct SerializeOptions {
+        /// Lay tensors out, and list them in the header, by name rather than
+        /// grouped by dtype. Tensors may then be unaligned. Default: false.
+        sort_keys: bool,
+        /// Minified JSON header rather than a pretty-printed, human readable
+        /// one. Default: true.
+        compact: bool,
+        /// Pad the header so the payload starts at a multiple of `align`
+        /// bytes in the file, 0 or 1 meaning no constraint. Default: 8.
+        align: usize,
+        /// Write `"__metadata__":{}` even without any entry. Default: false.
+        include_empty_metadata: bool,
+    }
+
+    /// Payload layouts for `serialize_grouped`. Putting similar tensors next
+    /// to each other helps general purpose compressors like zstd afterwards.
+    #[derive(Debug)]
+    enum TensorOrder {
+        /// Same as `serialize_packed`: by dtype, highest alignment first,
+        /// then by name.
+        Packed,
+        /// By module, i.e. the name up to its last `.`, then by byte size and
+        /// name within a module. Modules mixing dtypes leave tensors
+        /// misaligned, so this layout is not safe for zero-copy reads.
+        PrefixThenSize,
+        /// By dtype, highest alignment first, then shape, gathering
+        /// same-sized tensors of every layer, then by name. Every tensor
+        /// starts aligned to its dtype, as with `Packed`.
+        DtypeThenShape,
+    }
+
+    /// Coarse families of dtypes, from `dtype_group`, so C++ can switch over
+    /// a handful of cases instead of every dtype. New dtypes join one of
+    /// these groups rather than adding a case.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum DtypeGroup {
+        /// F16, BF16, F32 and F64.
+        Float,
+        /// I8, I16, I32 and I64.
+        SignedInt,
+        /// U8, U16, U32 and U64.
+        UnsignedInt,
+        /// BOOL.
+        Bool,
+        /// F4, F6_E2M3 and F6_E3M2, packed below one byte per value.
+        SubByteFloat,
+        /// F8_E5M2 and F8_E4M3.
+        Fp8,
+        /// F8_E8M0, a power of two scale for microscaling formats.
+        ScaleExponent,
+    }
+
+    /// A tensor borrowing its data from a buffer owned by the caller.
+    /// Serializing reads straight through `data`, no copy of the tensor
+    /// bytes is made, so C++ can export tensors it already holds in memory
+    /// as long as the buffers outlive the call.
+    ///
+    /// A `TensorView` never owns its bytes: one returned by the bindings
+    /// points into the buffer, vector or handle it was obtained from and
+    /// must not outlive it. Functions handing out data that outlives their
+    /// inputs return an `OwnedTensorView` instead, so the type alone tells
+    /// which lifetime rule applies.
     #[derive(Debug, PartialEq, Eq, Clone)]
     struct TensorView<'a> {
         shape: Vec<usize>,
@@ -63,6 +188,55 @@ mod ffi {
         data_len: usize,
     }
 
+    /// Same as `TensorView` but owning its data, for tensors read from disk
+    /// or produced by the bindings rather than borrowed from a caller buffer.
+    /// It stays valid after whatever it was built from is gone; borrow it
+    /// as a `TensorView` with `data_slice` to pass it back in.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    struct OwnedTensorView {
+        shape: Vec<usize>,
+        dtype: Dtype,
+        data: Vec<u8>,
+    }
+
+    /// Where the bytes of a tensor live in a file opened with `open_lazy`,
+    /// resolved to actual data with `LazySafeTensors::resolve`.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    struct LazyTensor {
+        name: String,
+        shape: Vec<usize>,
+        dtype: Dtype,
+        offset: usize,
+        data_len: usize,
+    }
+
+    /// Name, dtype and shape of a tensor, without its data.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    struct TensorSpec {
+        name: String,
+        dtype: Dtype,
+        shape: Vec<usize>,
+    }
+
+    /// Summary of a tensor's values, see `stats_f32`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct TensorStats {
+        min: f64,
+        max: f64,
+        mean: f64,
+        has_nan: bool,
+        has_inf: bool,
+    }
+
+    /// Result of `sanitize_floats`: the cleaned tensor and how many values
+    /// were replaced.
+    #[derive(Debug, Clone)]
+    struct SanitizedTensor {
+        tensor: OwnedTensorView,
+        nan_count: usize,
+        inf_count: usize,
+    }
+
     #[derive(Debug, Clone)]
     struct PairStrStr {
         key: String,
@@ -74,65 +248,895 @@ mod ffi {
         value: TensorView<'a>,
     }
 
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct PairStrUsize {
+        key: String,
+        value: usize,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct PairDtypeUsize {
+        key: Dtype,
+        value: usize,
+    }
+
+    #[derive(Debug, Clone)]
+    struct PairStrOwnedTensorView {
+        key: String,
+        value: OwnedTensorView,
+    }
+
+    /// One piece of a tensor held in scatter/gather buffers, borrowed for the
+    /// duration of the call only.
+    #[derive(Debug, Clone)]
+    struct ByteChunk<'a> {
+        data: &'a [u8],
+    }
+
+    /// Everything stored in a file, as returned by `load_file_full`.
+    #[derive(Debug, Clone)]
+    struct LoadedModel {
+        tensors: Vec<PairStrOwnedTensorView>,
+        metadata: Vec<PairStrStr>,
+    }
+
+    /// Where the bytes of a tensor live, in the payload of a `FlatModel` or
+    /// as listed by `build_index`.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    struct TensorLocation {
+        name: String,
+        shape: Vec<usize>,
+        dtype: Dtype,
+        offset: usize,
+        data_len: usize,
+    }
+
+    /// Every tensor of a file in a single buffer, as returned by `load_flat`.
+    #[derive(Debug, Clone)]
+    struct FlatModel {
+        payload: Vec<u8>,
+        tensors: Vec<TensorLocation>,
+    }
+
+    /// Overview of a file, as returned by `summarize`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct ModelSummary {
+        num_tensors: usize,
+        /// Size of all tensor data, i.e. the payload.
+        total_bytes: usize,
+        /// Number of tensors of each dtype present, in dtype order.
+        dtype_counts: Vec<PairDtypeUsize>,
+        has_metadata: bool,
+    }
+
+    /// How a file splits between header and tensor data, see `layout_info`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct LayoutInfo {
+        /// Everything before the payload: the 8-byte length prefix and the
+        /// JSON header, padding included.
+        header_bytes: usize,
+        payload_bytes: usize,
+        total_bytes: usize,
+    }
+
     // Rust types and signatures exposed to C++.
     extern "Rust" {
         // TODO(dp): implement with HashMap
         fn serialize(data: Vec<PairStrTensorView>, data_info: Vec<PairStrStr>) -> Result<Vec<u8>>;
 
+        fn serialize_empty(data_info: Vec<PairStrStr>) -> Result<Vec<u8>>;
+
+        fn serialize_with_tensor_meta(
+            data: Vec<PairStrTensorView>,
+            tensor_meta: Vec<PairStrStr>,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        fn default_serialize_options() -> SerializeOptions;
+
+        fn serialize_with_options(
+            data: Vec<PairStrTensorView>,
+            data_info: Vec<PairStrStr>,
+            options: &SerializeOptions,
+        ) -> Result<Vec<u8>>;
+
         fn serialize_to_file(
             data: Vec<PairStrTensorView>,
             data_info: Vec<PairStrStr>,
             path: &str,
         ) -> Result<()>;
 
-        fn deserialize(bytes: &[u8]) -> Result<Vec<PairStrTensorView>>;
+        fn serialize_to_file_hashed(
+            data: Vec<PairStrTensorView>,
+            data_info: Vec<PairStrStr>,
+            path: &str,
+        ) -> Result<String>;
+
+        fn serialize_to_file_progress(
+            data: Vec<PairStrTensorView>,
+            data_info: Vec<PairStrStr>,
+            path: &str,
+            progress: &SerializeProgress,
+        ) -> Result<()>;
+
+        fn serialize_packed(
+            data: Vec<PairStrTensorView>,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        fn serialize_ordered(
+            data: Vec<PairStrTensorView>,
+            key_order: Vec<String>,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        fn serialize_dedup(
+            data: Vec<PairStrTensorView>,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        fn serialize_grouped(
+            data: Vec<PairStrTensorView>,
+            order: TensorOrder,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        fn deserialize(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>>;
+
+        type TensorMap<'a>;
+
+        // `unsafe` only because cxx requires it to spell out lifetimes: the
+        // map and the views it hands out borrow from `bytes`.
+        unsafe fn deserialize_map<'a>(bytes: &'a [u8]) -> Result<Box<TensorMap<'a>>>;
+
+        unsafe fn get<'a>(self: &TensorMap<'a>, name: &str) -> Result<TensorView<'a>>;
+
+        fn keys(self: &TensorMap) -> Vec<String>;
+
+        // `unsafe` only because cxx requires it to spell out lifetimes: the
+        // view borrows from `buffer`.
+        unsafe fn get_tensor_ci<'a>(buffer: &'a [u8], name: &str) -> Result<TensorView<'a>>;
+
+        fn len(self: &TensorMap) -> usize;
+
+        fn deserialize_unchecked(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>>;
+
+        type TensorStream<'a>;
+
+        // `unsafe` only because cxx requires it to spell out lifetimes: the
+        // stream and the views it hands out borrow from `bytes`.
+        unsafe fn deserialize_stream<'a>(bytes: &'a [u8]) -> Result<Box<TensorStream<'a>>>;
+
+        unsafe fn next<'a>(self: &mut TensorStream<'a>) -> Result<PairStrTensorView<'a>>;
+
+        fn done(self: &TensorStream) -> bool;
+
+        fn deserialize_dedup(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>>;
+
+        fn deserialize_partial(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>>;
+
+        fn partial_missing(bytes: &[u8]) -> Result<Vec<String>>;
+
+        fn deserialize_ordered(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>>;
+
+        fn deserialize_by_size_desc(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>>;
 
         fn metadata(bytes: &[u8]) -> Result<Vec<PairStrStr>>;
+
+        fn tensor_metadata(bytes: &[u8], name: &str) -> Result<Vec<PairStrStr>>;
+
+        fn payload(buffer: &[u8]) -> Result<&[u8]>;
+
+        fn split_concatenated(buffer: &[u8]) -> Result<Vec<usize>>;
+
+        fn merge_metadata(
+            a: Vec<PairStrStr>,
+            b: Vec<PairStrStr>,
+            prefer_first: bool,
+        ) -> Vec<PairStrStr>;
+
+        fn is_safetensors(buffer: &[u8]) -> bool;
+
+        fn tensor_view_checked(
+            dtype: Dtype,
+            shape: Vec<usize>,
+            data: Vec<u8>,
+        ) -> Result<OwnedTensorView>;
+
+        fn data_slice(view: &OwnedTensorView) -> &[u8];
+
+        fn to_owned(view: &TensorView) -> OwnedTensorView;
+
+        fn is_owned(self: &TensorView) -> bool;
+
+        fn is_owned(self: &OwnedTensorView) -> bool;
+
+        fn data_mut(view: &mut OwnedTensorView) -> &mut [u8];
+
+        fn tensor_from_chunks(
+            dtype: Dtype,
+            shape: Vec<usize>,
+            chunks: Vec<ByteChunk>,
+        ) -> Result<OwnedTensorView>;
+
+        fn tensor_from_bool(shape: Vec<usize>, data: &[bool]) -> Result<OwnedTensorView>;
+
+        fn tensor_filled(
+            dtype: Dtype,
+            shape: Vec<usize>,
+            value_bytes: &[u8],
+        ) -> Result<OwnedTensorView>;
+
+        fn tensor_from_f64(shape: Vec<usize>, data: &[f64]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_f32(shape: Vec<usize>, data: &[f32]) -> Result<OwnedTensorView>;
+
+        // `unsafe` only because cxx requires it to spell out lifetimes: the
+        // view borrows the vector's storage.
+        unsafe fn tensor_from_cxx_f32<'a>(
+            shape: Vec<usize>,
+            data: &'a CxxVector<f32>,
+        ) -> Result<TensorView<'a>>;
+
+        fn tensor_from_i64(shape: Vec<usize>, data: &[i64]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_u64(shape: Vec<usize>, data: &[u64]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_i32(shape: Vec<usize>, data: &[i32]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_u32(shape: Vec<usize>, data: &[u32]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_i16(shape: Vec<usize>, data: &[i16]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_u16(shape: Vec<usize>, data: &[u16]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_i8(shape: Vec<usize>, data: &[i8]) -> Result<OwnedTensorView>;
+
+        fn tensor_from_u8(shape: Vec<usize>, data: &[u8]) -> Result<OwnedTensorView>;
+
+        fn dtype_from_name_lenient(name: &str) -> Result<Dtype>;
+
+        fn safetensors_version() -> String;
+
+        fn supported_dtypes() -> Vec<Dtype>;
+
+        fn dtype_bits(dtype: Dtype) -> Result<usize>;
+
+        fn promote_dtype(a: Dtype, b: Dtype) -> Result<Dtype>;
+
+        fn dtype_group(dtype: Dtype) -> Result<DtypeGroup>;
+
+        fn transcode(in_path: &str, out_path: &str, target_dtype: Dtype) -> Result<()>;
+
+        fn load_file_full(path: &str) -> Result<LoadedModel>;
+
+        fn load_flat(path: &str) -> Result<FlatModel>;
+
+        fn read_header_bytes(path: &str) -> Result<Vec<u8>>;
+
+        fn load_file_verified(
+            path: &str,
+            expected_sha256: &str,
+        ) -> Result<Vec<PairStrOwnedTensorView>>;
+
+        fn load_file_guarded(
+            path: &str,
+            max_header_bytes: usize,
+            max_total_bytes: usize,
+        ) -> Result<Vec<PairStrOwnedTensorView>>;
+
+        fn load_file_byteswap(path: &str) -> Result<Vec<PairStrOwnedTensorView>>;
+
+        fn write_index(shard_paths: Vec<String>, out_index_path: &str) -> Result<()>;
+
+        fn verify_sharded(index_path: &str) -> Result<Vec<String>>;
+
+        fn reshard(in_path: &str, num_shards: usize, out_prefix: &str) -> Result<Vec<String>>;
+
+        fn concat_files_to_file(paths: Vec<String>, out_path: &str) -> Result<()>;
+
+        fn extract(
+            in_path: &str,
+            out_path: &str,
+            names: Vec<String>,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<()>;
+
+        fn compare_files(
+            a_path: &str,
+            b_path: &str,
+            rtol: f64,
+            atol: f64,
+        ) -> Result<Vec<PairStrStr>>;
+
+        fn read_tensor_rows(
+            path: &str,
+            name: &str,
+            row_start: usize,
+            row_end: usize,
+        ) -> Result<OwnedTensorView>;
+
+        fn serialized_size(
+            data: &Vec<PairStrTensorView>,
+            data_info: &Vec<PairStrStr>,
+        ) -> Result<usize>;
+
+        fn stats_f32(view: &TensorView) -> Result<TensorStats>;
+
+        fn to_i64(view: &TensorView) -> Result<Vec<i64>>;
+
+        fn scalar_f64(view: &TensorView) -> Result<f64>;
+
+        fn scalar_i64(view: &TensorView) -> Result<i64>;
+
+        fn dtype_can_represent(view: &TensorView, target: Dtype) -> Result<bool>;
+
+        fn fp8_to_f32(data: &[u8], dtype: Dtype) -> Result<Vec<f32>>;
+
+        fn e8m0_to_scale_f32(data: &[u8]) -> Vec<f32>;
+
+        // `unsafe` only because cxx requires it to spell out lifetimes: the
+        // slices borrow from the buffer `view` points into.
+        unsafe fn as_f32_slice<'a>(view: &TensorView<'a>) -> Result<&'a [f32]>;
+
+        unsafe fn as_f64_slice<'a>(view: &TensorView<'a>) -> Result<&'a [f64]>;
+
+        unsafe fn as_i32_slice<'a>(view: &TensorView<'a>) -> Result<&'a [i32]>;
+
+        unsafe fn as_i64_slice<'a>(view: &TensorView<'a>) -> Result<&'a [i64]>;
+
+        fn downcast_in_place(view: OwnedTensorView, target: Dtype) -> Result<OwnedTensorView>;
+
+        fn sanitize_floats(
+            view: &TensorView,
+            replace_nan: f32,
+            replace_inf: f32,
+        ) -> Result<SanitizedTensor>;
+
+        fn split(view: &TensorView, axis: usize, parts: usize) -> Result<Vec<OwnedTensorView>>;
+
+        fn strides(view: &TensorView) -> Result<Vec<usize>>;
+
+        fn byte_strides(view: &TensorView) -> Result<Vec<usize>>;
+
+        fn get_tensor_colmajor(buffer: &[u8], name: &str) -> Result<OwnedTensorView>;
+
+        fn from_strided(
+            dtype: Dtype,
+            shape: Vec<usize>,
+            strides: Vec<usize>,
+            data: &[u8],
+        ) -> Result<OwnedTensorView>;
+
+        fn allclose(a: &TensorView, b: &TensorView, rtol: f64, atol: f64) -> Result<bool>;
+
+        fn dequantize_i8(
+            weights: &TensorView,
+            scales: &TensorView,
+            axis: usize,
+        ) -> Result<Vec<f32>>;
+
+        fn strip_prefix(buffer: &[u8], prefix: &str) -> Result<Vec<u8>>;
+
+        fn add_prefix(buffer: &[u8], prefix: &str) -> Result<Vec<u8>>;
+
+        fn remove_tensors(buffer: &[u8], names: Vec<String>) -> Result<Vec<u8>>;
+
+        fn build_index(buffer: &[u8], relative_to_file: bool) -> Result<Vec<TensorLocation>>;
+
+        fn serialize_with_offsets(
+            entries: Vec<TensorLocation>,
+            payload: &[u8],
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        fn normalize_names(buffer: &[u8], separator: &str) -> Result<Vec<u8>>;
+
+        fn diff_headers(a: &[u8], b: &[u8]) -> Result<Vec<String>>;
+
+        fn find(buffer: &[u8], pattern: &str) -> Result<Vec<String>>;
+
+        fn find_missing(buffer: &[u8], expected: Vec<String>) -> Result<Vec<String>>;
+
+        fn find_unexpected(buffer: &[u8], expected: Vec<String>) -> Result<Vec<String>>;
+
+        fn try_repair(buffer: &[u8]) -> Result<Vec<u8>>;
+
+        fn repack(buffer: &[u8], payload_align: usize) -> Result<Vec<u8>>;
+
+        fn check_shape(buffer: &[u8], name: &str, expected: Vec<usize>) -> Result<bool>;
+
+        fn padding_report(buffer: &[u8]) -> Result<Vec<PairStrUsize>>;
+
+        fn summarize(buffer: &[u8]) -> Result<ModelSummary>;
+
+        fn layout_info(buffer: &[u8]) -> Result<LayoutInfo>;
+
+        fn require_dtype(buffer: &[u8], dtype: Dtype) -> Result<()>;
+
+        fn count_parameters(buffer: &[u8], float_only: bool) -> Result<usize>;
+
+        fn rebuild_header(
+            tensors: Vec<TensorSpec>,
+            data_info: Vec<PairStrStr>,
+            payload_len: usize,
+        ) -> Result<Vec<u8>>;
+
+        fn load_url(url: &str) -> Result<Vec<PairStrOwnedTensorView>>;
+
+        fn load_url_tensor(url: &str, name: &str) -> Result<OwnedTensorView>;
+
+        type LazySafeTensors;
+
+        fn open_lazy(path: &str) -> Result<Box<LazySafeTensors>>;
+
+        fn names(self: &LazySafeTensors) -> Vec<String>;
+
+        fn view_lazy(self: &LazySafeTensors, name: &str) -> Result<LazyTensor>;
+
+        fn resolve(self: &LazySafeTensors, lazy: &LazyTensor) -> Result<OwnedTensorView>;
+
+        type TensorCollection;
+
+        fn new_collection() -> Box<TensorCollection>;
+
+        fn insert(self: &mut TensorCollection, name: &str, view: TensorView) -> Result<()>;
+
+        fn serialize_collection(
+            self: &TensorCollection,
+            data_info: Vec<PairStrStr>,
+        ) -> Result<Vec<u8>>;
+
+        type SerializeProgress;
+
+        fn new_serialize_progress() -> Box<SerializeProgress>;
+
+        fn written(self: &SerializeProgress) -> usize;
+
+        fn total(self: &SerializeProgress) -> usize;
+
+        type MmapSafeTensors;
+
+        fn open_mmap(path: &str, sequential: bool) -> Result<Box<MmapSafeTensors>>;
+
+        fn names(self: &MmapSafeTensors) -> Vec<String>;
+
+        // `unsafe` only because cxx requires it to spell out lifetimes: the
+        // view borrows from the mapping and must not outlive the handle.
+        unsafe fn get<'a>(self: &'a MmapSafeTensors, name: &str) -> Result<TensorView<'a>>;
+
+        fn advise_sequential(self: &MmapSafeTensors) -> Result<()>;
+
+        fn advise_random(self: &MmapSafeTensors) -> Result<()>;
+
+        fn prefetch(self: &MmapSafeTensors, name: &str) -> Result<()>;
+
+        type MutableSafeTensors;
+
+        fn open_file_mut(path: &str) -> Result<Box<MutableSafeTensors>>;
+
+        fn names(self: &MutableSafeTensors) -> Vec<String>;
+
+        // `unsafe` only to spell out that the slice borrows from the handle.
+        unsafe fn data_mut<'a>(
+            self: &'a mut MutableSafeTensors,
+            name: &str,
+        ) -> Result<&'a mut [u8]>;
+
+        fn flush(self: &MutableSafeTensors) -> Result<()>;
+
+        type ReaderLoader;
+
+        fn new_reader_loader() -> Box<ReaderLoader>;
+
+        fn offset(self: &ReaderLoader) -> usize;
+
+        fn remaining(self: &ReaderLoader) -> usize;
+
+        fn feed(self: &mut ReaderLoader, bytes: &[u8]) -> Result<()>;
+
+        fn finish(self: &mut ReaderLoader) -> Result<Vec<PairStrOwnedTensorView>>;
     }
 }
 
 fn serialize(
     data: Vec<PairStrTensorView>,
     data_info: Vec<PairStrStr>,
-) -> Result<Vec<u8>, SafeTensorError> {
+) -> Result<Vec<u8>, CxxError> {
     let tensors = prepare(data)?;
     let out = safetensors::tensor::serialize(tensors, convert_to_hashmap_string(data_info))?;
     Ok(out)
 }
 
-fn serialize_to_file(
+impl Default for SerializeOptions {
+    /// The layout `serialize` produces, same as `safetensors` itself.
+    fn default() -> Self {
+        SerializeOptions {
+            sort_keys: false,
+            compact: true,
+            align: N_LEN,
+            include_empty_metadata: false,
+        }
+    }
+}
+
+/// Options `serialize` uses, to start from when only some need changing.
+fn default_serialize_options() -> SerializeOptions {
+    SerializeOptions::default()
+}
+
+/// Serialize with the layout described by `options`. The defaults go
+/// through `serialize`, i.e. `safetensors` itself; only other options build
+/// the file here. Tensors are laid out back to back either way, so the
+/// output is a regular file whatever the options.
+fn serialize_with_options(
     data: Vec<PairStrTensorView>,
     data_info: Vec<PairStrStr>,
-    path: &str,
-) -> Result<(), SafeTensorError> {
-    let tensors = prepare(data)?;
-    safetensors::tensor::serialize_to_file(
-        tensors,
-        convert_to_hashmap_string(data_info),
-        path.as_ref(),
-    )?;
-    Ok(())
+    options: &SerializeOptions,
+) -> Result<Vec<u8>, CxxError> {
+    if *options == SerializeOptions::default() {
+        return serialize(data, data_info);
+    }
+    let layout = layout(prepare(data)?, data_info, options)?;
+    let mut out = Vec::with_capacity(layout.file_len());
+    out.extend((layout.header.len() as u64).to_le_bytes());
+    out.extend(&layout.header);
+    for data in layout.payloads {
+        out.extend_from_slice(data);
+    }
+    Ok(out)
 }
 
-fn deserialize(bytes: &[u8]) -> Result<Vec<PairStrTensorView>, SafeTensorError> {
-    let safetensor = SafeTensors::deserialize(bytes)?;
-    let tensors = safetensor.tensors();
+/// Padded header and payload pieces of a file, computed without copying any
+/// tensor data so writers can stream the payload.
+struct Layout<'data> {
+    header: Vec<u8>,
+    payloads: Vec<&'data [u8]>,
+    payload_len: usize,
+}
 
-    let mut items = Vec::with_capacity(tensors.len());
-    for (tensor_name, tensor) in tensors {
-        let mut shape = tensor.shape().to_vec();
-        let dtype = tensor.dtype();
-        if dtype == RDtype::F4 {
-            let n = shape.len();
-            shape[n - 1] /= 2; // F4 is stored as F8
-        }
-        let data = tensor.data();
-        let data_len = tensor.data_len();
+impl Layout<'_> {
+    /// Size of the whole file, length prefix included.
+    fn file_len(&self) -> usize {
+        N_LEN + self.header.len() + self.payload_len
+    }
+}
+
+/// Lay `tensors` out back to back as `options` describe.
+fn layout<'data>(
+    tensors: HashMap<String, TensorView<'data>>,
+    data_info: Vec<PairStrStr>,
+    options: &SerializeOptions,
+) -> Result<Layout<'data>, CxxError> {
+    let mut entries: Vec<_> = tensors.into_iter().collect();
+    // Names are unique, so an unstable sort gives the same order.
+    if options.sort_keys {
+        entries.sort_unstable_by(|(lname, _), (rname, _)| lname.cmp(rname));
+    } else {
+        entries.sort_unstable_by(|(lname, left), (rname, right)| {
+            right.dtype.cmp(&left.dtype).then(lname.cmp(rname))
+        });
+    }
+
+    // Names and shapes are moved into the header rather than cloned, which
+    // adds up for models with tens of thousands of tensors.
+    let mut infos = Vec::with_capacity(entries.len());
+    let mut payloads = Vec::with_capacity(entries.len());
+    let mut offset = 0;
+    for (name, tensor) in entries {
+        let info = TensorInfo {
+            dtype: tensor.dtype(),
+            shape: tensor.shape,
+            data_offsets: (offset, offset + tensor.data.len()),
+        };
+        offset += tensor.data.len();
+        payloads.push(tensor.data);
+        infos.push((name, info));
+    }
+    let data_info = match convert_to_hashmap_string(data_info) {
+        None if options.include_empty_metadata => Some(HashMap::new()),
+        data_info => data_info,
+    };
+    let metadata = Metadata::new(data_info, infos)?;
+
+    let header = if options.compact {
+        serde_json::to_vec(&metadata)
+    } else {
+        serde_json::to_vec_pretty(&metadata)
+    };
+    let mut header = header.map_err(SafeTensorError::JsonError)?;
+    // Padding the header puts the start of the payload on an `align` boundary.
+    let align = options.align.max(1);
+    header.resize((N_LEN + header.len()).next_multiple_of(align) - N_LEN, b' ');
+
+    Ok(Layout {
+        header,
+        payloads,
+        payload_len: offset,
+    })
+}
+
+fn serialize_to_file(
+    data: Vec<PairStrTensorView>,
+    data_info: Vec<PairStrStr>,
+    path: &str,
+) -> Result<(), CxxError> {
+    let tensors = prepare(data)?;
+    safetensors::tensor::serialize_to_file(
+        tensors,
+        convert_to_hashmap_string(data_info),
+        path.as_ref(),
+    )?;
+    Ok(())
+}
+
+/// A valid file holding no tensor, only `data_info` as its `__metadata__`,
+/// e.g. a placeholder for tooling expecting a file to exist.
+fn serialize_empty(data_info: Vec<PairStrStr>) -> Result<Vec<u8>, CxxError> {
+    serialize(Vec::new(), data_info)
+}
+
+/// Serialize with per-tensor notes, e.g. a quantization scheme, on top of the
+/// global `data_info`. The format only has one string to string
+/// `__metadata__` map, so notes are stored there under namespaced keys: the
+/// tensor name, a `:`, then the field, e.g. `encoder.weight:quant`. Fields
+/// can't contain `:`, tensor names can, the key being split at its last one.
+/// `tensor_meta` is given in that form, each key naming a tensor of `data`.
+/// Keys must be unique across `tensor_meta` and `data_info`, and `data_info`
+/// keys can't be of that form for a tensor of `data`, since they would read
+/// back as its notes. Read notes back with `tensor_metadata`.
+fn serialize_with_tensor_meta(
+    data: Vec<PairStrTensorView>,
+    tensor_meta: Vec<PairStrStr>,
+    mut data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    let names: HashSet<&str> = data.iter().map(|item| item.key.as_str()).collect();
+    let mut keys = HashSet::with_capacity(data_info.len() + tensor_meta.len());
+    for item in &data_info {
+        if let Some((tensor, _)) = item.key.rsplit_once(TENSOR_META_SEPARATOR) {
+            if names.contains(tensor) {
+                return Err(CxxError::ReservedMetadataKey(
+                    item.key.clone(),
+                    tensor.to_string(),
+                ));
+            }
+        }
+        if !keys.insert(item.key.as_str()) {
+            return Err(CxxError::DuplicateMetadataKey(item.key.clone()));
+        }
+    }
+    for item in &tensor_meta {
+        let Some((tensor, _)) = item
+            .key
+            .rsplit_once(TENSOR_META_SEPARATOR)
+            .filter(|(_, field)| !field.is_empty())
+        else {
+            return Err(CxxError::InvalidTensorMetaKey(item.key.clone()));
+        };
+        if !names.contains(tensor) {
+            return Err(SafeTensorError::TensorNotFound(tensor.to_string()).into());
+        }
+        if !keys.insert(item.key.as_str()) {
+            return Err(CxxError::DuplicateMetadataKey(item.key.clone()));
+        }
+    }
+    data_info.extend(tensor_meta);
+    serialize(data, data_info)
+}
+
+/// Serialize with tensors grouped by dtype, highest alignment first, then by
+/// name within a group. The payload is written without any gap, and since
+/// each tensor's byte size is a multiple of its element size, every tensor
+/// starts aligned to its dtype. Readers must not assume insertion order.
+///
+/// This is the order `safetensors` itself uses, so the layout matches
+/// `serialize`; this entry point makes it a documented guarantee.
+fn serialize_packed(
+    data: Vec<PairStrTensorView>,
+    data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    serialize(data, data_info)
+}
+
+/// Serialize with tensors laid out, and listed in the header, exactly in
+/// `key_order`. Every key of the order must be in `data` and every tensor of
+/// `data` must appear in the order, once. No padding is inserted, so unlike
+/// `serialize_packed` tensors are not guaranteed to be aligned.
+fn serialize_ordered(
+    data: Vec<PairStrTensorView>,
+    key_order: Vec<String>,
+    data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    write_ordered(prepare(data)?, key_order, data_info)
+}
+
+/// Serialize with tensors laid out in one of the fixed `order`s, without
+/// padding, so only the orders documented as such keep tensors aligned to
+/// their dtype. Ties are always broken by name so the output is
+/// deterministic.
+fn serialize_grouped(
+    data: Vec<PairStrTensorView>,
+    order: TensorOrder,
+    data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    let tensors = prepare(data)?;
+    let mut entries: Vec<_> = tensors.iter().collect();
+    match order {
+        TensorOrder::PrefixThenSize => {
+            fn prefix(name: &str) -> &str {
+                name.rsplit_once('.').map_or("", |(prefix, _)| prefix)
+            }
+            entries.sort_by(|(lname, left), (rname, right)| {
+                prefix(lname)
+                    .cmp(prefix(rname))
+                    .then(left.data.len().cmp(&right.data.len()))
+                    .then(lname.cmp(rname))
+            });
+        }
+        TensorOrder::DtypeThenShape => entries.sort_by(|(lname, left), (rname, right)| {
+            right
+                .dtype
+                .cmp(&left.dtype)
+                .then_with(|| (&left.shape, lname).cmp(&(&right.shape, rname)))
+        }),
+        // `Packed`, along with any value cast from an integer on the C++ side.
+        _ => entries.sort_by(|(lname, left), (rname, right)| {
+            right.dtype.cmp(&left.dtype).then(lname.cmp(rname))
+        }),
+    }
+    let key_order = entries.into_iter().map(|(name, _)| name.clone()).collect();
+    write_ordered(tensors, key_order, data_info)
+}
+
+/// Serialize storing tensors that share memory only once, e.g. tied
+/// embedding and output projection weights passed as views over the same
+/// buffer: every view with the same data pointer and length points at the
+/// same payload region. Equal bytes at different addresses, such as two
+/// zero-initialized tensors, are still written twice. The payload is laid
+/// out in the same order as `serialize`; the header lists tensors by name.
+///
+/// Shared regions make offsets overlap, which the format forbids: such a
+/// file is rejected by `deserialize` and by every other safetensors reader,
+/// Python included. Only `deserialize_dedup`, or `deserialize_unchecked`,
+/// can load it. Without duplicates the output is a regular file.
+fn serialize_dedup(
+    data: Vec<PairStrTensorView>,
+    data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    let tensors = prepare(data)?;
+    let mut entries: Vec<_> = tensors.iter().collect();
+    entries.sort_by(|(lname, left), (rname, right)| {
+        right.dtype.cmp(&left.dtype).then(lname.cmp(rname))
+    });
+
+    let mut header = serde_json::Map::with_capacity(entries.len() + 1);
+    if !data_info.is_empty() {
+        let metadata = data_info
+            .into_iter()
+            .map(|item| (item.key, item.value.into()))
+            .collect();
+        header.insert(
+            METADATA_KEY.to_string(),
+            serde_json::Value::Object(metadata),
+        );
+    }
+    let mut regions: HashMap<(*const u8, usize), (usize, usize)> =
+        HashMap::with_capacity(entries.len());
+    let mut payload = Vec::new();
+    for (name, tensor) in entries {
+        let region = (tensor.data.as_ptr(), tensor.data.len());
+        let data_offsets = *regions.entry(region).or_insert_with(|| {
+            let start = payload.len();
+            payload.extend_from_slice(tensor.data);
+            (start, payload.len())
+        });
+        let info = TensorInfo {
+            dtype: tensor.dtype(),
+            shape: tensor.shape.clone(),
+            data_offsets,
+        };
+        let info = serde_json::to_value(info).expect("TensorInfo is always serializable");
+        header.insert(name.clone(), info);
+    }
+    header::write_header(&header, &payload)
+}
+
+/// Lay `tensors` out back to back in `key_order`, which must list each of
+/// them exactly once.
+fn write_ordered(
+    mut tensors: HashMap<String, TensorView>,
+    key_order: Vec<String>,
+    data_info: Vec<PairStrStr>,
+) -> Result<Vec<u8>, CxxError> {
+    let mut infos = Vec::with_capacity(key_order.len());
+    let mut payload = Vec::new();
+    for name in key_order {
+        let Some(tensor) = tensors.remove(&name) else {
+            return Err(if infos.iter().any(|(seen, _)| seen == &name) {
+                CxxError::DuplicateName(name)
+            } else {
+                SafeTensorError::TensorNotFound(name).into()
+            });
+        };
+        let start = payload.len();
+        payload.extend_from_slice(tensor.data);
+        let info = TensorInfo {
+            dtype: tensor.dtype(),
+            shape: tensor.shape,
+            data_offsets: (start, payload.len()),
+        };
+        infos.push((name, info));
+    }
+    if let Some(name) = tensors.into_keys().min() {
+        return Err(CxxError::UnorderedTensor(name));
+    }
+
+    let metadata = Metadata::new(convert_to_hashmap_string(data_info), infos)?;
+    header::write_header(&metadata, &payload)
+}
+
+/// Size in bytes of the buffer `serialize` would produce for the same inputs,
+/// computed from the header it would emit without copying any tensor data.
+#[allow(clippy::ptr_arg)]
+fn serialized_size(
+    data: &Vec<PairStrTensorView>,
+    data_info: &Vec<PairStrStr>,
+) -> Result<usize, CxxError> {
+    // Same deduplication and ordering as `prepare` + `safetensors::serialize`,
+    // since the offsets, and therefore the header length, depend on it.
+    let unique: HashMap<&str, &TensorView> = data
+        .iter()
+        .map(|tensor| (tensor.key.as_str(), &tensor.value))
+        .collect();
+    let mut tensors: Vec<_> = unique.into_iter().collect();
+    tensors.sort_by(|(lname, left), (rname, right)| {
+        right.dtype.cmp(&left.dtype).then(lname.cmp(rname))
+    });
+
+    let mut header = serde_json::Map::with_capacity(tensors.len() + 1);
+    if !data_info.is_empty() {
+        let metadata = data_info
+            .iter()
+            .map(|item| (item.key.clone(), item.value.clone().into()))
+            .collect();
+        header.insert(
+            METADATA_KEY.to_string(),
+            serde_json::Value::Object(metadata),
+        );
+    }
+    let mut offset = 0;
+    for (name, tensor) in tensors {
+        let info = TensorInfo {
+            dtype: tensor.dtype.try_into()?,
+            shape: normalize_shape(tensor.dtype.try_into()?, &tensor.shape),
+            data_offsets: (offset, offset + tensor.data_len),
+        };
+        offset += tensor.data_len;
+        let info = serde_json::to_value(info).expect("TensorInfo is always serializable");
+        header.insert(name.to_string(), info);
+    }
+
+    let header_len = serde_json::Value::Object(header).to_string().len();
+    Ok(N_LEN + header_len.next_multiple_of(N_LEN) + offset)
+}
+
+/// Views over every tensor of `bytes`. Empty tensors load as views with no
+/// data, including header-only exports where every entry spans `(0, 0)`.
+fn deserialize(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>, CxxError> {
+    let safetensor = SafeTensors::deserialize(bytes)?;
+    let tensors = safetensor.tensors();
+
+    let mut items = Vec::with_capacity(tensors.len());
+    for (tensor_name, tensor) in tensors {
+        let dtype = tensor.dtype();
+        let shape = bridge_shape(dtype, tensor.shape());
+        let data = tensor.data();
+        let data_len = tensor.data_len();
         items.push(PairStrTensorView {
             key: tensor_name,
             value: TensorView {
                 shape,
-                dtype: dtype.into(),
+                dtype: dtype.try_into()?,
                 data,
                 data_len,
             },
@@ -141,11 +1145,375 @@ fn deserialize(bytes: &[u8]) -> Result<Vec<PairStrTensorView>, SafeTensorError>
     Ok(items)
 }
 
+/// Tensors of a buffer indexed by name, borrowing their data from it.
+pub struct TensorMap<'a> {
+    keys: Vec<String>,
+    tensors: HashMap<String, TensorView<'a>>,
+}
+
+/// Same as `deserialize`, returning a map for lookups by name instead of a
+/// list C++ has to scan. The buffer must outlive the map.
+fn deserialize_map(bytes: &[u8]) -> Result<Box<TensorMap<'_>>, CxxError> {
+    let (_, metadata) = SafeTensors::read_metadata(bytes)?;
+    let tensors = deserialize(bytes)?
+        .into_iter()
+        .map(|item| (item.key, item.value))
+        .collect();
+    Ok(Box::new(TensorMap {
+        keys: metadata.offset_keys(),
+        tensors,
+    }))
+}
+
+impl<'a> TensorMap<'a> {
+    /// Tensor `name`, its data borrowed from the deserialized buffer.
+    fn get(&self, name: &str) -> Result<TensorView<'a>, SafeTensorError> {
+        self.tensors
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))
+    }
+
+    /// Tensor names in the order their data is laid out.
+    fn keys(&self) -> Vec<String> {
+        self.keys.clone()
+    }
+
+    /// Number of tensors in the map.
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// Handle returned by `deserialize_stream`.
+pub struct TensorStream<'a> {
+    safetensors: SafeTensors<'a>,
+    keys: Vec<String>,
+    next: usize,
+}
+
+/// Push-style counterpart to `deserialize_map`: tensors are handed out one at
+/// a time in payload order, so C++ can upload and drop each one without the
+/// whole list being built (see `deserialize_each` in safetensors.hpp). cxx
+/// cannot take a C++ callback, hence the stream. The buffer is validated
+/// up front and must outlive the stream.
+fn deserialize_stream(bytes: &[u8]) -> Result<Box<TensorStream<'_>>, SafeTensorError> {
+    let safetensors = SafeTensors::deserialize(bytes)?;
+    let (_, metadata) = SafeTensors::read_metadata(bytes)?;
+    Ok(Box::new(TensorStream {
+        safetensors,
+        keys: metadata.offset_keys(),
+        next: 0,
+    }))
+}
+
+impl<'a> TensorStream<'a> {
+    /// Next tensor in payload order; fails with `EndOfStream` once `done`.
+    fn next(&mut self) -> Result<PairStrTensorView<'a>, CxxError> {
+        let name = self.keys.get(self.next).ok_or(CxxError::EndOfStream)?;
+        let tensor = self.safetensors.tensor(name)?;
+        self.next += 1;
+        Ok(PairStrTensorView {
+            key: name.clone(),
+            value: TensorView {
+                shape: bridge_shape(tensor.dtype(), tensor.shape()),
+                dtype: tensor.dtype().try_into()?,
+                data: tensor.data(),
+                data_len: tensor.data_len(),
+            },
+        })
+    }
+
+    /// Whether every tensor has been handed out.
+    fn done(&self) -> bool {
+        self.next == self.keys.len()
+    }
+}
+
+/// Tensor of `buffer` whose name equals `name` ignoring case, e.g.
+/// `Encoder.Weight` for `encoder.weight`. Fails with `TensorNotFound` if none
+/// does, and with `AmbiguousName` if several do, rather than picking one.
+fn get_tensor_ci<'a>(buffer: &'a [u8], name: &str) -> Result<TensorView<'a>, CxxError> {
+    let safetensor = SafeTensors::deserialize(buffer)?;
+    let lowercase = name.to_lowercase();
+    let mut matches: Vec<_> = safetensor
+        .names()
+        .into_iter()
+        .filter(|candidate| candidate.to_lowercase() == lowercase)
+        .collect();
+    matches.sort();
+    let found = match matches.as_slice() {
+        [] => return Err(SafeTensorError::TensorNotFound(name.to_string()).into()),
+        [found] => *found,
+        _ => {
+            return Err(CxxError::AmbiguousName(
+                name.to_string(),
+                matches.join(", "),
+            ))
+        }
+    };
+
+    let tensor = safetensor.tensor(found)?;
+    Ok(TensorView {
+        shape: bridge_shape(tensor.dtype(), tensor.shape()),
+        dtype: tensor.dtype().try_into()?,
+        data: tensor.data(),
+        data_len: tensor.data_len(),
+    })
+}
+
+/// Split `bytes` into its JSON header and whatever follows it, as long as
+/// the header itself is complete.
+fn split_header(bytes: &[u8]) -> Result<(&[u8], &[u8]), SafeTensorError> {
+    let Some((prefix, rest)) = bytes.split_first_chunk::<N_LEN>() else {
+        return Err(SafeTensorError::HeaderTooSmall);
+    };
+    let n = header_len(*prefix)?;
+    if rest.len() < n {
+        return Err(SafeTensorError::InvalidHeaderLength);
+    }
+    Ok(rest.split_at(n))
+}
+
+/// The tensor data region of `buffer`, everything after the header,
+/// borrowed without copying. Tensors sit at their header `data_offsets`
+/// within it, so custom loaders can address any of them directly. The header
+/// is validated like `deserialize` does.
+fn payload(buffer: &[u8]) -> Result<&[u8], SafeTensorError> {
+    let (n, _) = SafeTensors::read_metadata(buffer)?;
+    Ok(&buffer[N_LEN + n..])
+}
+
+/// Offsets at which each file starts in a blob of safetensors files written
+/// back to back, e.g. `[0, 4096]` for two files, the first being 4096 bytes.
+/// Each file is fully validated, and the blob must end exactly after the
+/// last one.
+fn split_concatenated(buffer: &[u8]) -> Result<Vec<usize>, SafeTensorError> {
+    let mut starts = Vec::new();
+    let mut start = 0;
+    loop {
+        let (payload_start, metadata) = partial_header(&buffer[start..])?;
+        let end = (start + payload_start)
+            .checked_add(data_len(&metadata))
+            .filter(|&end| end <= buffer.len())
+            .ok_or(SafeTensorError::MetadataIncompleteBuffer)?;
+        SafeTensors::deserialize(&buffer[start..end])?;
+        starts.push(start);
+        start = end;
+        if start == buffer.len() {
+            return Ok(starts);
+        }
+    }
+}
+
+/// Payload size `metadata` calls for, the end of its last tensor.
+fn data_len(metadata: &Metadata) -> usize {
+    metadata
+        .tensors()
+        .values()
+        .map(|info| info.data_offsets.1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Header of a possibly truncated buffer, along with the offset of its
+/// payload, as long as the header itself is complete.
+fn partial_header(bytes: &[u8]) -> Result<(usize, Metadata), SafeTensorError> {
+    let (header, _) = split_header(bytes)?;
+    Ok((N_LEN + header.len(), parse_header(header)?))
+}
+
+/// Same as `deserialize`, in header order, without checking that tensors
+/// are laid out back to back and cover the whole payload: the
+/// `InvalidOffset` and `MetadataIncompleteBuffer` checks are skipped. Meant
+/// for trusted, freshly written files on hot paths. Each view is still
+/// checked to lie within `bytes` and to match its dtype and shape, so a bad
+/// file gives an error rather than out of bounds reads, and a name listed
+/// twice fails with `DuplicateName`.
+fn deserialize_unchecked(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>, CxxError> {
+    let (header, payload) = split_header(bytes)?;
+    let tensors = header_tensors(header)?;
+
+    let mut seen = HashSet::with_capacity(tensors.len());
+    let mut items = Vec::with_capacity(tensors.len());
+    for (name, info) in tensors {
+        if !seen.insert(name.clone()) {
+            return Err(CxxError::DuplicateName(name));
+        }
+        let (start, end) = info.data_offsets;
+        let data = payload
+            .get(start..end)
+            .ok_or(SafeTensorError::MetadataIncompleteBuffer)?;
+        check_view(info.dtype, &info.shape, data.len())?;
+        items.push(PairStrTensorView {
+            key: name,
+            value: TensorView {
+                shape: bridge_shape(info.dtype, &info.shape),
+                dtype: info.dtype.try_into()?,
+                data,
+                data_len: data.len(),
+            },
+        });
+    }
+    Ok(items)
+}
+
+/// Same as `deserialize`, in header order, also accepting tensors sharing
+/// the exact same payload region, as written by `serialize_dedup`. Apart
+/// from such shared regions, tensors must still be laid out back to back
+/// and cover the whole payload; partial overlaps are rejected with
+/// `InvalidOffset`.
+fn deserialize_dedup(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>, CxxError> {
+    let (header, payload) = split_header(bytes)?;
+    let tensors = header_tensors(header)?;
+
+    let mut regions: Vec<_> = tensors
+        .iter()
+        .map(|(name, info)| (info.data_offsets, name))
+        .collect();
+    regions.sort();
+    regions.dedup_by_key(|(offsets, _)| *offsets);
+    let mut end = 0;
+    for ((start, stop), name) in regions {
+        if start != end || stop < start {
+            return Err(SafeTensorError::InvalidOffset(name.clone()).into());
+        }
+        end = stop;
+    }
+    if end != payload.len() {
+        return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+    }
+    deserialize_unchecked(bytes)
+}
+
+/// Tensors fully present in `bytes`, e.g. a file still being downloaded,
+/// in payload order. Those cut off by the end of the buffer are skipped,
+/// see `partial_missing`. The header must be complete.
+fn deserialize_partial(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>, CxxError> {
+    let (start, metadata) = partial_header(bytes)?;
+    let payload = &bytes[start..];
+    let mut items = Vec::new();
+    for name in metadata.offset_keys() {
+        let info = metadata
+            .info(&name)
+            .expect("offset_keys come from the metadata");
+        let Some(data) = payload.get(info.data_offsets.0..info.data_offsets.1) else {
+            break;
+        };
+        items.push(PairStrTensorView {
+            key: name,
+            value: TensorView {
+                shape: bridge_shape(info.dtype, &info.shape),
+                dtype: info.dtype.try_into()?,
+                data,
+                data_len: data.len(),
+            },
+        });
+    }
+    Ok(items)
+}
+
+/// Tensors `deserialize_partial` skips because their data isn't fully in
+/// `bytes` yet, in payload order.
+fn partial_missing(bytes: &[u8]) -> Result<Vec<String>, SafeTensorError> {
+    let (start, metadata) = partial_header(bytes)?;
+    let available = bytes.len() - start;
+    Ok(metadata
+        .offset_keys()
+        .into_iter()
+        .filter(|name| {
+            metadata
+                .info(name)
+                .is_some_and(|info| info.data_offsets.1 > available)
+        })
+        .collect())
+}
+
+/// Same as `deserialize`, with tensors in the order their keys appear in
+/// the header JSON rather than in hash order.
+fn deserialize_ordered(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>, CxxError> {
+    let mut items = deserialize(bytes)?;
+    let (n, _) = SafeTensors::read_metadata(bytes)?;
+    let keys = header::header_keys(&bytes[N_LEN..N_LEN + n])?;
+    let positions: HashMap<&str, usize> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (key.as_str(), i))
+        .collect();
+    items.sort_by_key(|item| positions[item.key.as_str()]);
+    Ok(items)
+}
+
+/// Same as `deserialize`, largest tensors in bytes first, e.g. to allocate
+/// them before the small ones and limit fragmentation. Ties are broken by
+/// name so the order is deterministic.
+fn deserialize_by_size_desc(bytes: &[u8]) -> Result<Vec<PairStrTensorView<'_>>, CxxError> {
+    let mut items = deserialize(bytes)?;
+    items.sort_unstable_by(|left, right| {
+        right
+            .value
+            .data
+            .len()
+            .cmp(&left.value.data.len())
+            .then_with(|| left.key.cmp(&right.key))
+    });
+    Ok(items)
+}
+
+/// Same as `deserialize`, copying the data out so it can outlive `bytes`.
+fn deserialize_owned(bytes: &[u8]) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+    let items = deserialize(bytes)?
+        .into_iter()
+        .map(|item| PairStrOwnedTensorView {
+            key: item.key,
+            value: OwnedTensorView {
+                shape: item.value.shape,
+                dtype: item.value.dtype,
+                data: item.value.data.to_vec(),
+            },
+        })
+        .collect();
+    Ok(items)
+}
+
+/// The `__metadata__` entries of `bytes`, which only needs to hold the
+/// header, e.g. as returned by `read_header_bytes`.
 fn metadata(bytes: &[u8]) -> Result<Vec<PairStrStr>, SafeTensorError> {
-    let (_n, metadata) = SafeTensors::read_metadata(bytes)?;
-    let Some(metadata) = &metadata.metadata() else {
+    let (_, metadata) = partial_header(bytes)?;
+    Ok(metadata_pairs(&metadata))
+}
+
+/// Notes stored for tensor `name` by `serialize_with_tensor_meta`, as field
+/// and value pairs sorted by field, the `name:` namespace stripped. Notes of
+/// a tensor whose name merely starts with `name:` are not included. Fails if
+/// `name` is not a tensor of `bytes`, which only needs to hold the header.
+fn tensor_metadata(bytes: &[u8], name: &str) -> Result<Vec<PairStrStr>, SafeTensorError> {
+    let (_, metadata) = partial_header(bytes)?;
+    if metadata.info(name).is_none() {
+        return Err(SafeTensorError::TensorNotFound(name.to_string()));
+    }
+    let Some(data_info) = metadata.metadata() else {
         return Ok(Vec::new());
     };
+    let mut items: Vec<_> = data_info
+        .iter()
+        .filter_map(|(key, value)| {
+            let (tensor, field) = key.rsplit_once(TENSOR_META_SEPARATOR)?;
+            (tensor == name).then(|| PairStrStr {
+                key: field.to_string(),
+                value: value.clone(),
+            })
+        })
+        .collect();
+    items.sort_by(|left, right| left.key.cmp(&right.key));
+    Ok(items)
+}
+
+/// The `__metadata__` entries of a parsed header, empty if it has none.
+fn metadata_pairs(metadata: &Metadata) -> Vec<PairStrStr> {
+    let Some(metadata) = &metadata.metadata() else {
+        return Vec::new();
+    };
     let mut items = Vec::with_capacity(metadata.len());
     for (key, value) in metadata {
         items.push(PairStrStr {
@@ -153,12 +1521,12 @@ fn metadata(bytes: &[u8]) -> Result<Vec<PairStrStr>, SafeTensorError> {
             value: value.to_string(),
         });
     }
-    Ok(items)
+    items
 }
 
 // private
 impl View for TensorView<'_> {
-    fn data(&self) -> Cow<[u8]> {
+    fn data(&self) -> Cow<'_, [u8]> {
         self.data.into()
     }
 
@@ -171,26 +1539,198 @@ impl View for TensorView<'_> {
     }
 
     fn dtype(&self) -> RDtype {
-        self.dtype.into()
+        // Views only reach `View` users through `prepare_tensor`, which
+        // rejects dtypes outside the enum.
+        self.dtype
+            .try_into()
+            .expect("dtype checked by prepare_tensor")
+    }
+}
+
+/// A tensor whose data is either borrowed from an input buffer or produced
+/// by a conversion, so both can be serialized together.
+struct CowTensor<'data> {
+    dtype: RDtype,
+    shape: Vec<usize>,
+    data: Cow<'data, [u8]>,
+}
+
+impl View for CowTensor<'_> {
+    fn data(&self) -> Cow<'_, [u8]> {
+        self.data.as_ref().into()
+    }
+
+    fn data_len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    fn dtype(&self) -> RDtype {
+        self.dtype
     }
 }
 
 fn prepare(
-    tensor_dict: Vec<PairStrTensorView>,
-) -> Result<HashMap<String, TensorView>, SafeTensorError> {
+    tensor_dict: Vec<PairStrTensorView<'_>>,
+) -> Result<HashMap<String, TensorView<'_>>, CxxError> {
     let mut tensors = HashMap::with_capacity(tensor_dict.len());
     for tensor in tensor_dict {
-        let mut shape: Vec<usize> = tensor.value.shape().to_vec();
-        let dtype: RDtype = tensor.value.dtype();
+        let value = prepare_tensor(&tensor.key, tensor.value)?;
+        tensors.insert(tensor.key, value);
+    }
+    Ok(tensors)
+}
 
-        if dtype == RDtype::F4 {
-            let n = shape.len();
-            shape[n - 1] *= 2;
-        };
+/// Validate a single tensor about to be serialized under `name`, returning
+/// it with its file-side shape.
+fn prepare_tensor<'a>(name: &str, mut value: TensorView<'a>) -> Result<TensorView<'a>, CxxError> {
+    if name == METADATA_KEY {
+        return Err(CxxError::ReservedName(name.to_string()));
+    }
+    let dtype = RDtype::try_from(value.dtype)?;
+    // Only F4 shapes differ on the file side, spare the copy otherwise.
+    if dtype == RDtype::F4 {
+        value.shape = normalize_shape(dtype, &value.shape);
+    }
+    let checked = if value.data_len == value.data.len() {
+        check_view(dtype, &value.shape, value.data.len())
+    } else {
+        Err(SafeTensorError::InvalidTensorView(
+            dtype,
+            value.shape.clone(),
+            value.data.len(),
+        ))
+    };
+    match checked {
+        Ok(()) => Ok(value),
+        Err(err) => Err(CxxError::InvalidTensor(name.to_string(), err)),
+    }
+}
 
-        tensors.insert(tensor.key, tensor.value);
+/// Payload size in bytes of a tensor of the given dtype and file-side shape,
+/// without overflowing on hostile shapes.
+fn view_size(dtype: RDtype, shape: &[usize]) -> Result<usize, SafeTensorError> {
+    let n_bits = shape
+        .iter()
+        .try_fold(dtype.bitsize(), |acc, &dim| acc.checked_mul(dim))
+        .ok_or(SafeTensorError::ValidationOverflow)?;
+    if n_bits % 8 != 0 {
+        return Err(SafeTensorError::MisalignedSlice);
     }
-    Ok(tensors)
+    Ok(n_bits / 8)
+}
+
+/// Check that `n_bytes` is exactly the payload size of a tensor of the given
+/// dtype and file-side shape.
+fn check_view(dtype: RDtype, shape: &[usize], n_bytes: usize) -> Result<(), SafeTensorError> {
+    if view_size(dtype, shape)? != n_bytes {
+        return Err(SafeTensorError::InvalidTensorView(
+            dtype,
+            shape.to_vec(),
+            n_bytes,
+        ));
+    }
+    Ok(())
+}
+
+/// Build a tensor after checking, without overflowing, that `data` holds
+/// exactly the bytes `shape` and `dtype` call for.
+fn tensor_view_checked(
+    dtype: Dtype,
+    shape: Vec<usize>,
+    data: Vec<u8>,
+) -> Result<OwnedTensorView, CxxError> {
+    check_view(
+        dtype.try_into()?,
+        &normalize_shape(dtype.try_into()?, &shape),
+        data.len(),
+    )?;
+    Ok(OwnedTensorView { shape, dtype, data })
+}
+
+/// Bytes of `view` as a `rust::Slice`, the type `TensorView::data` already
+/// borrows as, so read paths can handle owned and borrowed tensors alike
+/// without copying. The slice is only valid while `view` lives and its data
+/// is left unmodified.
+fn data_slice(view: &OwnedTensorView) -> &[u8] {
+    &view.data
+}
+
+/// Copy of `view` owning its data, the way out of zero-copy reads when a
+/// tensor has to outlive the buffer or handle it borrows from, e.g. keeping
+/// a few tensors of a mapped file once the map is dropped.
+fn to_owned(view: &TensorView) -> OwnedTensorView {
+    OwnedTensorView {
+        shape: view.shape.clone(),
+        dtype: view.dtype,
+        data: view.data.to_vec(),
+    }
+}
+
+/// Bytes of `view` as a mutable `rust::Slice`, e.g. to tweak a few values of
+/// a freshly built tensor before serializing it. Only owned tensors can be
+/// modified; the slice has a fixed length, so dtype and shape stay valid.
+fn data_mut(view: &mut OwnedTensorView) -> &mut [u8] {
+    &mut view.data
+}
+
+impl TensorView<'_> {
+    /// Always false: the data belongs to whatever the view was obtained
+    /// from, which must outlive it.
+    fn is_owned(&self) -> bool {
+        false
+    }
+}
+
+impl OwnedTensorView {
+    /// Always true, for glue code generic over both view types.
+    fn is_owned(&self) -> bool {
+        true
+    }
+}
+
+/// Header length declared by the 8-byte little-endian prefix of a file.
+fn header_len(prefix: [u8; N_LEN]) -> Result<usize, SafeTensorError> {
+    let n: usize = u64::from_le_bytes(prefix)
+        .try_into()
+        .map_err(|_| SafeTensorError::HeaderTooLarge)?;
+    if n > MAX_HEADER_SIZE {
+        return Err(SafeTensorError::HeaderTooLarge);
+    }
+    Ok(n)
+}
+
+/// Parse the JSON header on its own, for when the payload is not at hand.
+/// Offsets are checked to be contiguous, not to match any payload length.
+fn parse_header(header_bytes: &[u8]) -> Result<Metadata, SafeTensorError> {
+    let string = std::str::from_utf8(header_bytes).map_err(SafeTensorError::InvalidHeader)?;
+    serde_json::from_str(string).map_err(SafeTensorError::InvalidHeaderDeserialization)
+}
+
+/// The shape as seen from C++, where F4 elements are stored as F8 pairs.
+fn bridge_shape(dtype: RDtype, shape: &[usize]) -> Vec<usize> {
+    let mut shape = shape.to_vec();
+    if dtype == RDtype::F4 {
+        if let Some(last) = shape.last_mut() {
+            *last /= 2; // F4 is stored as F8
+        }
+    }
+    shape
+}
+
+/// The shape as stored in the file, the inverse of `bridge_shape`.
+/// Scalars (shape `[]`) are kept as is, never coerced to `[1]`.
+fn normalize_shape(dtype: RDtype, shape: &[usize]) -> Vec<usize> {
+    let mut shape = shape.to_vec();
+    if dtype == RDtype::F4 {
+        if let Some(last) = shape.last_mut() {
+            *last *= 2;
+        }
+    }
+    shape
 }
 
 fn convert_to_hashmap_string(dict: Vec<PairStrStr>) -> Option<HashMap<String, String>> {
@@ -204,3 +1744,887 @@ fn convert_to_hashmap_string(dict: Vec<PairStrStr>) -> Option<HashMap<String, St
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn pair<'a>(
+        key: &str,
+        dtype: Dtype,
+        shape: Vec<usize>,
+        data: &'a [u8],
+    ) -> PairStrTensorView<'a> {
+        PairStrTensorView {
+            key: key.to_string(),
+            value: TensorView {
+                shape,
+                dtype,
+                data,
+                data_len: data.len(),
+            },
+        }
+    }
+
+    #[test]
+    fn serialized_size_matches_serialize() {
+        let weight = f32_bytes(&[1.0; 6]);
+        let bias = [0u8; 3];
+        let data = || {
+            vec![
+                pair("weight", Dtype::F32, vec![2, 3], &weight),
+                pair("bias", Dtype::U8, vec![3], &bias),
+            ]
+        };
+        let data_info = vec![PairStrStr {
+            key: "format".to_string(),
+            value: "pt".to_string(),
+        }];
+
+        let expected = serialized_size(&data(), &data_info).unwrap();
+        assert_eq!(expected, serialize(data(), data_info).unwrap().len());
+        let expected = serialized_size(&data(), &Vec::new()).unwrap();
+        assert_eq!(expected, serialize(data(), Vec::new()).unwrap().len());
+    }
+
+    #[test]
+    fn serialize_options() {
+        let weight = f32_bytes(&[1.0; 6]);
+        let bias = [0u8; 3];
+        let data = || {
+            vec![
+                pair("z.weight", Dtype::F32, vec![2, 3], &weight),
+                pair("a.bias", Dtype::U8, vec![3], &bias),
+            ]
+        };
+        let data_info = || {
+            vec![PairStrStr {
+                key: "format".to_string(),
+                value: "pt".to_string(),
+            }]
+        };
+        let expected = safetensors::tensor::serialize(
+            prepare(data()).unwrap(),
+            convert_to_hashmap_string(data_info()),
+        )
+        .unwrap();
+        assert_eq!(serialize(data(), data_info()).unwrap(), expected);
+        let defaults = default_serialize_options();
+        assert_eq!(
+            serialize_with_options(data(), data_info(), &defaults).unwrap(),
+            expected
+        );
+        // Streaming writers lay files out here rather than in `safetensors`.
+        let layout = layout(prepare(data()).unwrap(), data_info(), &defaults).unwrap();
+        let mut streamed = Vec::new();
+        streamed.extend((layout.header.len() as u64).to_le_bytes());
+        streamed.extend(&layout.header);
+        layout
+            .payloads
+            .iter()
+            .for_each(|data| streamed.extend(*data));
+        assert_eq!(streamed, expected);
+
+        let options = SerializeOptions {
+            sort_keys: true,
+            compact: false,
+            align: 64,
+            include_empty_metadata: true,
+        };
+        let out = serialize_with_options(data(), Vec::new(), &options).unwrap();
+        let (n, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        assert_eq!((N_LEN + n) % 64, 0);
+        assert_eq!(metadata.offset_keys(), vec!["a.bias", "z.weight"]);
+        assert!(metadata.metadata().as_ref().unwrap().is_empty());
+        let header = std::str::from_utf8(&out[N_LEN..N_LEN + n]).unwrap();
+        assert!(header.contains('\n'));
+        assert!(header.contains("\"__metadata__\": {}"));
+        let loaded = SafeTensors::deserialize(&out).unwrap();
+        assert_eq!(loaded.tensor("z.weight").unwrap().data(), &weight[..]);
+
+        let unaligned = SerializeOptions {
+            align: 0,
+            ..options
+        };
+        let out = serialize_with_options(data(), Vec::new(), &unaligned).unwrap();
+        assert!(SafeTensors::deserialize(&out).is_ok());
+    }
+
+    #[test]
+    fn unicode_names() {
+        let weight = f32_bytes(&[1.0, 2.0]);
+        let bias = [7u8; 2];
+        let data = vec![
+            pair("层.权重", Dtype::F32, vec![2], &weight),
+            pair("층.bias", Dtype::U8, vec![2], &bias),
+        ];
+        let data_info = vec![PairStrStr {
+            key: "描述".to_string(),
+            value: "モデル".to_string(),
+        }];
+        let out = serialize(data, data_info).unwrap();
+
+        // Names are written as raw UTF-8, not `\u` escapes, and the offsets
+        // after them still line up.
+        let n = header_len(out[..N_LEN].try_into().unwrap()).unwrap();
+        let header = std::str::from_utf8(&out[N_LEN..N_LEN + n]).unwrap();
+        assert!(header.contains("\"层.权重\""));
+
+        let mut tensors = deserialize(&out).unwrap();
+        tensors.sort_by(|a, b| a.key.cmp(&b.key));
+        let keys: Vec<_> = tensors.iter().map(|item| item.key.as_str()).collect();
+        assert_eq!(keys, vec!["层.权重", "층.bias"]);
+        assert_eq!(tensors[0].value.data, weight.as_slice());
+        assert_eq!(tensors[1].value.data, &bias);
+
+        let info = metadata(&out).unwrap();
+        assert_eq!(info[0].key, "描述");
+        assert_eq!(info[0].value, "モデル");
+    }
+
+    #[test]
+    fn packed_layout() {
+        let a = [1u8; 3];
+        let b = f32_bytes(&[1.0; 3]);
+        let c = [0u8; 2];
+        let d = 1f64.to_le_bytes();
+        let data = vec![
+            pair("a", Dtype::U8, vec![3], &a),
+            pair("b", Dtype::F32, vec![3], &b),
+            pair("c", Dtype::I16, vec![1], &c),
+            pair("d", Dtype::F64, vec![1], &d),
+        ];
+        let out = serialize_packed(data, Vec::new()).unwrap();
+
+        let (_, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        assert_eq!(metadata.offset_keys(), vec!["d", "b", "c", "a"]);
+        for name in metadata.offset_keys() {
+            let info = metadata.info(&name).unwrap();
+            assert_eq!(info.data_offsets.0 % (info.dtype.bitsize() / 8), 0);
+        }
+    }
+
+    #[test]
+    fn scalar_roundtrip() {
+        let value = f32_bytes(&[3.5]);
+        let data = vec![pair("scale", Dtype::F32, vec![], &value)];
+        let out = serialize(data, Vec::new()).unwrap();
+
+        let (_, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        assert!(metadata.info("scale").unwrap().shape.is_empty());
+
+        let tensors = deserialize(&out).unwrap();
+        assert_eq!(tensors.len(), 1);
+        let scale = &tensors[0].value;
+        assert!(scale.shape.is_empty());
+        assert_eq!(scale.dtype, Dtype::F32);
+        assert_eq!(scale.data, value.as_slice());
+    }
+
+    #[test]
+    fn empty_model() {
+        let data_info = vec![PairStrStr {
+            key: "format".to_string(),
+            value: "pt".to_string(),
+        }];
+        let out = serialize_empty(data_info).unwrap();
+
+        // The payload is empty and the offset coverage check still passes.
+        assert!(payload(&out).unwrap().is_empty());
+        assert!(deserialize(&out).unwrap().is_empty());
+        let info = metadata(&out).unwrap();
+        assert_eq!(info.len(), 1);
+        assert_eq!(
+            (info[0].key.as_str(), info[0].value.as_str()),
+            ("format", "pt")
+        );
+
+        let out = serialize_empty(Vec::new()).unwrap();
+        assert!(deserialize(&out).unwrap().is_empty());
+        assert!(metadata(&out).unwrap().is_empty());
+        assert!(matches!(
+            deserialize(&[out.as_slice(), &[0]].concat()),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+    }
+
+    #[test]
+    fn truncated_buffers() {
+        let weight = f32_bytes(&[1.0; 4]);
+        let data = vec![pair("weight", Dtype::F32, vec![4], &weight)];
+        let out = serialize(data, Vec::new()).unwrap();
+        let header_end = out.len() - weight.len();
+
+        // Cut within the payload: the header parses but doesn't cover the buffer.
+        assert!(matches!(
+            deserialize(&out[..out.len() - 1]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        assert!(matches!(
+            deserialize(&out[..header_end]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        // Cut within the header: the declared length doesn't fit.
+        assert!(matches!(
+            deserialize(&out[..header_end - 1]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidHeaderLength
+            ))
+        ));
+        assert!(matches!(
+            deserialize(&out[..N_LEN]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidHeaderLength
+            ))
+        ));
+        // Cut within the length prefix.
+        assert!(matches!(
+            deserialize(&out[..N_LEN - 1]),
+            Err(CxxError::SafeTensorError(SafeTensorError::HeaderTooSmall))
+        ));
+    }
+
+    #[test]
+    fn reserved_metadata_name() {
+        let value = f32_bytes(&[1.0]);
+        let data = vec![pair("__metadata__", Dtype::F32, vec![1], &value)];
+        assert!(matches!(
+            serialize(data, Vec::new()),
+            Err(CxxError::ReservedName(name)) if name == "__metadata__"
+        ));
+
+        let data = vec![pair("__metadata__", Dtype::F32, vec![1], &value)];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("reserved_metadata_name.safetensors");
+        assert!(serialize_to_file(data, Vec::new(), path.to_str().unwrap()).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unknown_dtype() {
+        // C++ can cast any integer to `Dtype`.
+        let value = f32_bytes(&[1.0]);
+        let data = vec![pair("a", Dtype { repr: 200 }, vec![1], &value)];
+        assert!(matches!(
+            serialize(data, Vec::new()),
+            Err(CxxError::UnknownDtype(_))
+        ));
+    }
+
+    #[test]
+    fn ordered_layout() {
+        let a = f32_bytes(&[1.0, 2.0]);
+        let b = [7u8; 3];
+        let data = || {
+            vec![
+                pair("a", Dtype::F32, vec![2], &a),
+                pair("b", Dtype::U8, vec![3], &b),
+            ]
+        };
+        let order = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+
+        let out = serialize_ordered(data(), order(&["b", "a"]), Vec::new()).unwrap();
+        let (n, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        assert_eq!(metadata.offset_keys(), vec!["b", "a"]);
+        assert_eq!(metadata.info("b").unwrap().data_offsets, (0, 3));
+        assert_eq!(metadata.info("a").unwrap().data_offsets, (3, 11));
+        let header = std::str::from_utf8(&out[N_LEN..N_LEN + n]).unwrap();
+        assert!(header.find("\"b\"").unwrap() < header.find("\"a\"").unwrap());
+        let loaded = SafeTensors::deserialize(&out).unwrap();
+        assert_eq!(loaded.tensor("a").unwrap().data(), &a[..]);
+
+        assert!(matches!(
+            serialize_ordered(data(), order(&["b", "a", "c"]), Vec::new()),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(name))) if name == "c"
+        ));
+        assert!(matches!(
+            serialize_ordered(data(), order(&["b"]), Vec::new()),
+            Err(CxxError::UnorderedTensor(name)) if name == "a"
+        ));
+        assert!(matches!(
+            serialize_ordered(data(), order(&["b", "b", "a"]), Vec::new()),
+            Err(CxxError::DuplicateName(name)) if name == "b"
+        ));
+    }
+
+    #[test]
+    fn grouped_layouts() {
+        let small = [0u8; 2];
+        let large = [0u8; 8];
+        let data = || {
+            vec![
+                pair("layers.1.w", Dtype::F32, vec![2], &large),
+                pair("layers.0.w", Dtype::F32, vec![2], &large),
+                pair("layers.0.b", Dtype::U8, vec![2], &small),
+                pair("layers.1.b", Dtype::U8, vec![2], &small),
+                pair("head", Dtype::I16, vec![4], &large),
+            ]
+        };
+        let layout = |order| {
+            let out = serialize_grouped(data(), order, Vec::new()).unwrap();
+            let (_, metadata) = SafeTensors::read_metadata(&out).unwrap();
+            metadata.offset_keys()
+        };
+        let aligned = |order| {
+            let out = serialize_grouped(data(), order, Vec::new()).unwrap();
+            let (_, metadata) = SafeTensors::read_metadata(&out).unwrap();
+            metadata
+                .tensors()
+                .values()
+                .all(|info| info.data_offsets.0 % info.dtype.bitsize().div_ceil(8) == 0)
+        };
+
+        assert_eq!(
+            layout(TensorOrder::PrefixThenSize),
+            vec![
+                "head",
+                "layers.0.b",
+                "layers.0.w",
+                "layers.1.b",
+                "layers.1.w"
+            ]
+        );
+        assert_eq!(
+            layout(TensorOrder::DtypeThenShape),
+            vec![
+                "layers.0.w",
+                "layers.1.w",
+                "head",
+                "layers.0.b",
+                "layers.1.b"
+            ]
+        );
+        assert!(aligned(TensorOrder::DtypeThenShape));
+        assert!(!aligned(TensorOrder::PrefixThenSize));
+        assert_eq!(
+            serialize_grouped(data(), TensorOrder::Packed, Vec::new()).unwrap(),
+            serialize_packed(data(), Vec::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn dedup_roundtrip() {
+        let tied = f32_bytes(&[1.0, 2.0, 3.0, 4.0]);
+        let bias = [5u8; 2];
+        let bias_copy = bias;
+        let data = || {
+            vec![
+                pair("embed", Dtype::F32, vec![2, 2], &tied),
+                pair("lm_head", Dtype::F32, vec![2, 2], &tied),
+                pair("bias", Dtype::U8, vec![2], &bias),
+                pair("scale", Dtype::U8, vec![2], &bias_copy),
+            ]
+        };
+
+        let out = serialize_dedup(data(), Vec::new()).unwrap();
+        let plain = serialize(data(), Vec::new()).unwrap();
+        assert_eq!(plain.len() - out.len(), tied.len());
+        assert!(matches!(
+            deserialize(&out),
+            Err(CxxError::SafeTensorError(SafeTensorError::InvalidOffset(_)))
+        ));
+
+        let mut items = deserialize_dedup(&out).unwrap();
+        items.sort_by(|a, b| a.key.cmp(&b.key));
+        let keys: Vec<_> = items.iter().map(|item| item.key.as_str()).collect();
+        assert_eq!(keys, vec!["bias", "embed", "lm_head", "scale"]);
+        assert_eq!(items[1].value.data, tied.as_slice());
+        assert_eq!(items[1].value.data.as_ptr(), items[2].value.data.as_ptr());
+        // Equal bytes in separate buffers aren't merged.
+        assert_eq!(items[0].value.data, &bias);
+        assert_eq!(items[3].value.data, &bias);
+        assert_ne!(items[0].value.data.as_ptr(), items[3].value.data.as_ptr());
+        assert_eq!(deserialize_dedup(&plain).unwrap().len(), 4);
+
+        // Without duplicates, the output is a regular file.
+        let unique = vec![pair("bias", Dtype::U8, vec![2], &bias)];
+        assert!(deserialize(&serialize_dedup(unique, Vec::new()).unwrap()).is_ok());
+
+        let header = concat!(
+            r#"{"a":{"dtype":"U8","shape":[4],"data_offsets":[0,4]},"#,
+            r#""b":{"dtype":"U8","shape":[4],"data_offsets":[2,6]}}"#,
+        );
+        let mut overlapping = (header.len() as u64).to_le_bytes().to_vec();
+        overlapping.extend(header.as_bytes());
+        overlapping.extend([0; 6]);
+        assert!(matches!(
+            deserialize_dedup(&overlapping),
+            Err(CxxError::SafeTensorError(SafeTensorError::InvalidOffset(name))) if name == "b"
+        ));
+    }
+
+    #[test]
+    fn per_tensor_metadata() {
+        let meta = |items: &[(&str, &str)]| {
+            items
+                .iter()
+                .map(|&(key, value)| PairStrStr {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let data = || {
+            vec![
+                pair("w", Dtype::U8, vec![1], &[1]),
+                pair("w:scale", Dtype::U8, vec![1], &[2]),
+            ]
+        };
+        let tensor_meta = meta(&[
+            ("w:quant", "int8"),
+            ("w:group", "128"),
+            ("w:scale:quant", "none"),
+        ]);
+        let out =
+            serialize_with_tensor_meta(data(), tensor_meta, meta(&[("format", "pt")])).unwrap();
+
+        let notes = |name| {
+            tensor_metadata(&out, name)
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.key, item.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            notes("w"),
+            vec![
+                ("group".into(), "128".into()),
+                ("quant".into(), "int8".into())
+            ]
+        );
+        assert_eq!(notes("w:scale"), vec![("quant".into(), "none".into())]);
+        assert_eq!(metadata(&out).unwrap().len(), 4);
+        assert!(matches!(
+            tensor_metadata(&out, "b"),
+            Err(SafeTensorError::TensorNotFound(name)) if name == "b"
+        ));
+
+        let err =
+            |tensor_meta, data_info| serialize_with_tensor_meta(data(), tensor_meta, data_info);
+        assert!(matches!(
+            err(meta(&[("b:quant", "int8")]), Vec::new()),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(name))) if name == "b"
+        ));
+        for key in ["w:", "quant"] {
+            assert!(matches!(
+                err(meta(&[(key, "int8")]), Vec::new()),
+                Err(CxxError::InvalidTensorMetaKey(k)) if k == key
+            ));
+        }
+        assert!(matches!(
+            err(meta(&[("w:quant", "int8"), ("w:quant", "fp8")]), Vec::new()),
+            Err(CxxError::DuplicateMetadataKey(key)) if key == "w:quant"
+        ));
+        assert!(matches!(
+            err(meta(&[("w:quant", "int8")]), meta(&[("w:quant", "fp8")])),
+            Err(CxxError::ReservedMetadataKey(key, tensor)) if key == "w:quant" && tensor == "w"
+        ));
+        // Only keys naming a tensor are reserved.
+        assert!(err(Vec::new(), meta(&[("model:version", "2")])).is_ok());
+    }
+
+    #[test]
+    fn deserialize_largest_first() {
+        let data = vec![
+            pair("small", Dtype::U8, vec![2], &[0; 2]),
+            pair("large", Dtype::F32, vec![4], &[0; 16]),
+            pair("b_same", Dtype::U16, vec![2], &[0; 4]),
+            pair("a_same", Dtype::U8, vec![4], &[0; 4]),
+            pair("empty", Dtype::F64, vec![0], &[]),
+        ];
+        let out = serialize(data, Vec::new()).unwrap();
+
+        let items = deserialize_by_size_desc(&out).unwrap();
+        let keys: Vec<_> = items.iter().map(|item| item.key.as_str()).collect();
+        assert_eq!(keys, vec!["large", "a_same", "b_same", "small", "empty"]);
+        assert_eq!(items[0].value.data.len(), 16);
+    }
+
+    #[test]
+    fn ordered_deserialize() {
+        // Hand-written header listing keys neither sorted nor in offset order.
+        let header = concat!(
+            r#"{"zeta":{"dtype":"U8","shape":[1],"data_offsets":[2,3]},"#,
+            r#""__metadata__":{"format":"pt"},"#,
+            r#""alpha":{"dtype":"U8","shape":[1],"data_offsets":[1,2]},"#,
+            r#""mid":{"dtype":"U8","shape":[1],"data_offsets":[0,1]}}"#,
+        );
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes.extend([0, 1, 2]);
+
+        let items = deserialize_ordered(&bytes).unwrap();
+        let keys: Vec<_> = items.iter().map(|item| item.key.as_str()).collect();
+        assert_eq!(keys, vec!["zeta", "alpha", "mid"]);
+        assert_eq!(items[0].value.data, &[2]);
+        assert!(deserialize_ordered(&bytes[..N_LEN + 4]).is_err());
+    }
+
+    #[test]
+    fn unchecked_deserialize() {
+        // A gap between the tensors and trailing bytes, both rejected by
+        // `deserialize`.
+        let header = concat!(
+            r#"{"b":{"dtype":"I16","shape":[2],"data_offsets":[4,8]},"#,
+            r#""__metadata__":{"format":"pt"},"#,
+            r#""a":{"dtype":"U8","shape":[2],"data_offsets":[0,2]}}"#,
+        );
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes.extend([1, 2, 0, 0, 3, 0, 4, 0, 0]);
+        assert!(deserialize(&bytes).is_err());
+
+        let items = deserialize_unchecked(&bytes).unwrap();
+        let keys: Vec<_> = items.iter().map(|item| item.key.as_str()).collect();
+        assert_eq!(keys, vec!["b", "a"]);
+        assert_eq!(items[0].value.data, &[3, 0, 4, 0]);
+        assert_eq!(items[1].value.data, &[1, 2]);
+
+        assert!(matches!(
+            deserialize_unchecked(&bytes[..bytes.len() - 2]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+
+        let header = r#"{"a":{"dtype":"I16","shape":[2],"data_offsets":[0,2]}}"#;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes.extend([0, 0]);
+        assert!(matches!(
+            deserialize_unchecked(&bytes),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(..)
+            ))
+        ));
+
+        let header = concat!(
+            r#"{"a":{"dtype":"U8","shape":[2],"data_offsets":[0,2]},"#,
+            r#""a":{"dtype":"U8","shape":[2],"data_offsets":[0,2]}}"#,
+        );
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes.extend([1, 2]);
+        for result in [deserialize_unchecked(&bytes), deserialize_dedup(&bytes)] {
+            assert!(matches!(
+                result,
+                Err(CxxError::DuplicateName(name)) if name == "a"
+            ));
+        }
+    }
+
+    #[test]
+    fn partial_buffer() {
+        let a = [1u8; 4];
+        let b = [2u8; 4];
+        let c = [3u8; 4];
+        let data = vec![
+            pair("a", Dtype::U8, vec![4], &a),
+            pair("b", Dtype::U8, vec![4], &b),
+            pair("c", Dtype::U8, vec![4], &c),
+        ];
+        let out = serialize(data, Vec::new()).unwrap();
+        let header_end = out.len() - 12;
+
+        let partial = &out[..header_end + 6];
+        let items = deserialize_partial(partial).unwrap();
+        let keys: Vec<_> = items.iter().map(|item| item.key.as_str()).collect();
+        assert_eq!(keys, vec!["a"]);
+        assert_eq!(items[0].value.data, &a);
+        assert_eq!(partial_missing(partial).unwrap(), vec!["b", "c"]);
+
+        assert_eq!(deserialize_partial(&out).unwrap().len(), 3);
+        assert!(partial_missing(&out).unwrap().is_empty());
+        assert!(deserialize_partial(&out[..header_end]).unwrap().is_empty());
+        assert!(matches!(
+            deserialize_partial(&out[..header_end - 1]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidHeaderLength
+            ))
+        ));
+    }
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let weight = f32_bytes(&[1.0]);
+        let data = vec![
+            pair("Encoder.Weight", Dtype::F32, vec![1], &weight),
+            pair("head.bias", Dtype::U8, vec![1], &[7]),
+            pair("HEAD.BIAS", Dtype::U8, vec![1], &[8]),
+        ];
+        let out = serialize(data, Vec::new()).unwrap();
+
+        let view = get_tensor_ci(&out, "encoder.weight").unwrap();
+        assert_eq!((view.dtype, view.data), (Dtype::F32, &weight[..]));
+        assert!(matches!(
+            get_tensor_ci(&out, "decoder.weight"),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                _
+            )))
+        ));
+        assert!(matches!(
+            get_tensor_ci(&out, "Head.Bias"),
+            Err(CxxError::AmbiguousName(_, found)) if found == "HEAD.BIAS, head.bias"
+        ));
+    }
+
+    #[test]
+    fn concatenated_files() {
+        let weight = f32_bytes(&[1.0, 2.0]);
+        let first = serialize(vec![pair("w", Dtype::F32, vec![2], &weight)], Vec::new()).unwrap();
+        let second = serialize_empty(Vec::new()).unwrap();
+        let mut blob = first.clone();
+        blob.extend(&second);
+        blob.extend(&first);
+
+        let starts = split_concatenated(&blob).unwrap();
+        assert_eq!(starts, vec![0, first.len(), first.len() + second.len()]);
+        let last = deserialize(&blob[starts[2]..]).unwrap();
+        assert_eq!(last[0].value.data, weight);
+
+        assert!(split_concatenated(&blob[..blob.len() - 1]).is_err());
+        blob.push(0);
+        assert!(split_concatenated(&blob).is_err());
+        assert!(split_concatenated(&[]).is_err());
+    }
+
+    #[test]
+    fn zero_length_payload() {
+        // Header-only export: the one tensor is empty and spans `(0, 0)`.
+        let header = br#"{"empty":{"dtype":"F32","shape":[0],"data_offsets":[0,0]}}"#;
+        let mut out = (header.len() as u64).to_le_bytes().to_vec();
+        out.extend(header);
+
+        for items in [
+            deserialize(&out).unwrap(),
+            deserialize_unchecked(&out).unwrap(),
+            deserialize_ordered(&out).unwrap(),
+        ] {
+            assert_eq!(items.len(), 1);
+            assert_eq!(items[0].key, "empty");
+            assert_eq!(items[0].value.dtype, Dtype::F32);
+            assert_eq!(items[0].value.shape, vec![0]);
+            assert!(items[0].value.data.is_empty());
+            assert_eq!(items[0].value.data_len, 0);
+        }
+        let mut stream = deserialize_stream(&out).unwrap();
+        assert!(stream.next().unwrap().value.data.is_empty());
+        assert!(stream.done());
+    }
+
+    #[test]
+    fn stream_in_payload_order() {
+        let a = f32_bytes(&[1.0, 2.0]);
+        let b = [7u8; 3];
+        let data = vec![
+            pair("b", Dtype::U8, vec![3], &b),
+            pair("a", Dtype::F32, vec![2], &a),
+        ];
+        let out = serialize(data, Vec::new()).unwrap();
+        let mut stream = deserialize_stream(&out).unwrap();
+        let mut seen = Vec::new();
+        while !stream.done() {
+            let item = stream.next().unwrap();
+            seen.push((item.key, item.value.data.to_vec()));
+        }
+        assert_eq!(
+            seen,
+            vec![("a".to_string(), a.clone()), ("b".to_string(), b.to_vec())]
+        );
+        assert!(matches!(stream.next(), Err(CxxError::EndOfStream)));
+
+        assert!(deserialize_stream(&out[..out.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn map_lookup() {
+        let a = f32_bytes(&[1.0, 2.0]);
+        let b = [7u8; 3];
+        let data = vec![
+            pair("a", Dtype::F32, vec![2], &a),
+            pair("b", Dtype::U8, vec![3], &b),
+        ];
+        let out = serialize(data, Vec::new()).unwrap();
+
+        let map = deserialize_map(&out).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.keys(), vec!["a", "b"]);
+        let b_view = map.get("b").unwrap();
+        assert_eq!(b_view.data, &b);
+        assert_eq!(b_view.data.as_ptr(), out[out.len() - 3..].as_ptr());
+        assert!(matches!(
+            map.get("c"),
+            Err(SafeTensorError::TensorNotFound(name)) if name == "c"
+        ));
+    }
+
+    #[test]
+    fn payload_region() {
+        let a = f32_bytes(&[1.0, 2.0]);
+        let b = [7u8; 3];
+        let data = vec![
+            pair("a", Dtype::F32, vec![2], &a),
+            pair("b", Dtype::U8, vec![3], &b),
+        ];
+        let out = serialize(data, Vec::new()).unwrap();
+
+        let region = payload(&out).unwrap();
+        assert_eq!(region.len(), 11);
+        assert_eq!(region.as_ptr(), out[out.len() - 11..].as_ptr());
+        let (_, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        let (start, end) = metadata.info("b").unwrap().data_offsets;
+        assert_eq!(&region[start..end], &b);
+        assert!(matches!(
+            payload(&out[..out.len() - 1]),
+            Err(SafeTensorError::MetadataIncompleteBuffer)
+        ));
+    }
+
+    #[test]
+    fn header_is_minified() {
+        // `safetensors` writes the header with `serde_json::to_string`, so a
+        // separate compact variant would save nothing. Only the trailing
+        // alignment padding is whitespace.
+        let weight = f32_bytes(&[1.0; 4]);
+        let data = vec![
+            pair("a.weight", Dtype::F32, vec![2, 2], &weight),
+            pair("b.weight", Dtype::F32, vec![4], &weight),
+        ];
+        let data_info = vec![PairStrStr {
+            key: "format".to_string(),
+            value: "pt".to_string(),
+        }];
+        let out = serialize(data, data_info).unwrap();
+
+        let (n, _) = SafeTensors::read_metadata(&out).unwrap();
+        let header = std::str::from_utf8(&out[N_LEN..N_LEN + n]).unwrap();
+        let json = header.trim_end_matches(' ');
+        assert!(!json.contains(char::is_whitespace));
+        assert!(header.len() - json.len() < N_LEN);
+        assert_eq!(SafeTensors::deserialize(&out).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn mismatched_data_length() {
+        let short = [0u8; 23];
+        let data = vec![pair("weight", Dtype::F32, vec![2, 3], &short)];
+        let err = serialize(data, Vec::new()).unwrap_err();
+        assert!(matches!(
+            &err,
+            CxxError::InvalidTensor(name, SafeTensorError::InvalidTensorView(RDtype::F32, shape, 23))
+                if name == "weight" && shape == &[2, 3]
+        ));
+        assert!(err.to_string().contains("`weight`"));
+
+        let value = f32_bytes(&[1.0; 6]);
+        let mut tensor = pair("weight", Dtype::F32, vec![2, 3], &value);
+        tensor.value.data_len += 1;
+        assert!(matches!(
+            serialize(vec![tensor], Vec::new()),
+            Err(CxxError::InvalidTensor(name, SafeTensorError::InvalidTensorView(..)))
+                if name == "weight"
+        ));
+
+        let data = vec![pair("huge", Dtype::F32, vec![usize::MAX, 2], &value)];
+        assert!(matches!(
+            serialize(data, Vec::new()),
+            Err(CxxError::InvalidTensor(
+                _,
+                SafeTensorError::ValidationOverflow
+            ))
+        ));
+    }
+
+    #[test]
+    fn checked_view() {
+        let view = tensor_view_checked(Dtype::I16, vec![2, 2], vec![0; 8]).unwrap();
+        assert_eq!(view.shape, vec![2, 2]);
+        assert!(tensor_view_checked(Dtype::F4, vec![3], vec![0; 3]).is_ok());
+        assert!(tensor_view_checked(Dtype::BOOL, vec![], vec![1]).is_ok());
+
+        assert!(matches!(
+            tensor_view_checked(Dtype::I16, vec![2, 2], vec![0; 7]),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(RDtype::I16, _, 7)
+            ))
+        ));
+        assert!(matches!(
+            tensor_view_checked(Dtype::F64, vec![usize::MAX / 4, 2], Vec::new()),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::ValidationOverflow
+            ))
+        ));
+        assert!(matches!(
+            tensor_view_checked(Dtype::F6_E2M3, vec![3], vec![0; 2]),
+            Err(CxxError::SafeTensorError(SafeTensorError::MisalignedSlice))
+        ));
+    }
+
+    #[test]
+    fn borrowed_data_slice() {
+        let view = tensor_view_checked(Dtype::U8, vec![3], vec![1, 2, 3]).unwrap();
+        let slice = data_slice(&view);
+        assert_eq!(slice, &[1, 2, 3]);
+        assert_eq!(slice.as_ptr(), view.data.as_ptr());
+        assert!(view.is_owned());
+        let borrowed = TensorView {
+            shape: view.shape.clone(),
+            dtype: view.dtype,
+            data: slice,
+            data_len: slice.len(),
+        };
+        assert!(!borrowed.is_owned());
+    }
+
+    #[test]
+    fn owned_outlives_handle() {
+        let weight = f32_bytes(&[1.0, 2.0, 3.0]);
+        let data = vec![pair("weight", Dtype::F32, vec![3], &weight)];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("to_owned.safetensors");
+        std::fs::write(&path, serialize(data, Vec::new()).unwrap()).unwrap();
+
+        let handle = open_mmap(path.to_str().unwrap(), false).unwrap();
+        let owned = to_owned(&handle.get("weight").unwrap());
+        drop(handle);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(owned.shape, vec![3]);
+        assert_eq!(owned.dtype, Dtype::F32);
+        assert_eq!(owned.data, weight);
+    }
+
+    #[test]
+    fn mutable_data() {
+        let mut view = tensor_view_checked(Dtype::U16, vec![2], vec![1, 0, 2, 0]).unwrap();
+        data_mut(&mut view)[2..].copy_from_slice(&7u16.to_le_bytes());
+        assert_eq!(view.data, vec![1, 0, 7, 0]);
+        assert_eq!(data_mut(&mut view).len(), 4);
+    }
+
+    #[test]
+    fn f4_shape_roundtrip() {
+        // Two bytes hold four F4 values, seen from C++ as two F8 pairs.
+        let packed = [0x21, 0x43];
+        let data = vec![pair("packed", Dtype::F4, vec![2], &packed)];
+        let out = serialize(data, Vec::new()).unwrap();
+
+        let (_, metadata) = SafeTensors::read_metadata(&out).unwrap();
+        assert_eq!(metadata.info("packed").unwrap().shape, vec![4]);
+
+        let tensors = deserialize(&out).unwrap();
+        assert_eq!(tensors[0].value.shape, vec![2]);
+        assert_eq!(tensors[0].value.data, &packed);
+    }
+}
@@ -0,0 +1,250 @@
+//! Memory-mapped handles over a file on disk, the mapping living as long as
+//! the handle.
+use crate::error::CxxError;
+use crate::ffi::TensorView;
+use crate::{bridge_shape, N_LEN};
+use memmap2::{Mmap, MmapMut};
+use safetensors::tensor::Metadata;
+use safetensors::{SafeTensorError, SafeTensors};
+use std::fs::{File, OpenOptions};
+
+/// Handle returned by `open_mmap`.
+pub struct MmapSafeTensors {
+    mmap: Mmap,
+    header_len: usize,
+    metadata: Metadata,
+}
+
+/// Map `path` and validate its header and offsets. With `sequential`, the
+/// kernel is told the mapping will be read front to back, which enlarges
+/// readahead on the first full pass over a large file.
+pub fn open_mmap(path: &str, sequential: bool) -> Result<Box<MmapSafeTensors>, CxxError> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read-only; like every mmap based loader, the
+    // file must not be truncated or modified while the handle is alive.
+    let mmap = unsafe { Mmap::map(&file)? };
+    SafeTensors::deserialize(&mmap)?;
+    let (header_len, metadata) = SafeTensors::read_metadata(&mmap)?;
+    let handle = Box::new(MmapSafeTensors {
+        mmap,
+        header_len,
+        metadata,
+    });
+    if sequential {
+        handle.advise_sequential()?;
+    }
+    Ok(handle)
+}
+
+impl MmapSafeTensors {
+    /// Tensor names in the order their data is laid out in the file.
+    pub fn names(&self) -> Vec<String> {
+        self.metadata.offset_keys()
+    }
+
+    /// Tensor `name`, its data pointing straight into the mapping: nothing
+    /// is copied, pages are read from disk as the data is accessed. The view
+    /// is only valid as long as the handle.
+    pub fn get(&self, name: &str) -> Result<TensorView<'_>, CxxError> {
+        let info = self
+            .metadata
+            .info(name)
+            .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+        let (begin, end) = info.data_offsets;
+        let start = N_LEN + self.header_len;
+        Ok(TensorView {
+            shape: bridge_shape(info.dtype, &info.shape),
+            dtype: info.dtype.try_into()?,
+            data: &self.mmap[start + begin..start + end],
+            data_len: end - begin,
+        })
+    }
+
+    /// Hint that the mapping will be read front to back. No-op where
+    /// `madvise` is not available.
+    pub fn advise_sequential(&self) -> Result<(), CxxError> {
+        #[cfg(unix)]
+        self.mmap.advise(memmap2::Advice::Sequential)?;
+        Ok(())
+    }
+
+    /// Hint that tensors will be read in no particular order, turning off
+    /// readahead. No-op where `madvise` is not available.
+    pub fn advise_random(&self) -> Result<(), CxxError> {
+        #[cfg(unix)]
+        self.mmap.advise(memmap2::Advice::Random)?;
+        Ok(())
+    }
+
+    /// Start loading the pages of tensor `name` without waiting for them,
+    /// e.g. the next layer's weights while the current one computes. Uses
+    /// `madvise(WILLNEED)` where available, otherwise reads one byte per
+    /// page, which does block.
+    pub fn prefetch(&self, name: &str) -> Result<(), CxxError> {
+        let info = self
+            .metadata
+            .info(name)
+            .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+        let (begin, end) = info.data_offsets;
+        if begin == end {
+            return Ok(());
+        }
+        let start = N_LEN + self.header_len + begin;
+        #[cfg(unix)]
+        self.mmap
+            .advise_range(memmap2::Advice::WillNeed, start, end - begin)?;
+        #[cfg(not(unix))]
+        {
+            const PAGE: usize = 4096;
+            let bytes = &self.mmap[start..start + end - begin];
+            let touched = bytes.iter().step_by(PAGE).fold(0u8, |acc, &b| acc ^ b);
+            std::hint::black_box(touched);
+        }
+        Ok(())
+    }
+}
+
+/// Handle returned by `open_file_mut`.
+pub struct MutableSafeTensors {
+    mmap: MmapMut,
+    header_len: usize,
+    metadata: Metadata,
+}
+
+/// Map `path` read-write, e.g. for a quantization pass rewriting weights in
+/// place. Only tensor bytes can be changed this way: the header is never
+/// touched, so dtypes, shapes and offsets stay as they are, and the file
+/// keeps its size since the mapping can't grow or shrink.
+pub fn open_file_mut(path: &str) -> Result<Box<MutableSafeTensors>, CxxError> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    // SAFETY: the file must not be truncated or modified by anyone else
+    // while the handle is alive.
+    let mmap = unsafe { MmapMut::map_mut(&file)? };
+    SafeTensors::deserialize(&mmap)?;
+    let (header_len, metadata) = SafeTensors::read_metadata(&mmap)?;
+    Ok(Box::new(MutableSafeTensors {
+        mmap,
+        header_len,
+        metadata,
+    }))
+}
+
+impl MutableSafeTensors {
+    /// Tensor names in the order their data is laid out in the file.
+    pub fn names(&self) -> Vec<String> {
+        self.metadata.offset_keys()
+    }
+
+    /// Bytes of tensor `name`, writable in place. The slice has exactly the
+    /// tensor's size; writes show up in the file once flushed or when the
+    /// handle is dropped.
+    pub fn data_mut(&mut self, name: &str) -> Result<&mut [u8], CxxError> {
+        let info = self
+            .metadata
+            .info(name)
+            .ok_or_else(|| SafeTensorError::TensorNotFound(name.to_string()))?;
+        let start = N_LEN + self.header_len;
+        let (begin, end) = info.data_offsets;
+        Ok(&mut self.mmap[start + begin..start + end])
+    }
+
+    /// Write modified bytes back to disk, blocking until done.
+    pub fn flush(&self) -> Result<(), CxxError> {
+        self.mmap.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CowTensor;
+    use safetensors::Dtype as RDtype;
+    use std::borrow::Cow;
+
+    #[test]
+    fn mmap_open() {
+        let data: Vec<u8> = (0..8u32).flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = vec![(
+            "weight",
+            CowTensor {
+                dtype: RDtype::U32,
+                shape: vec![2, 4],
+                data: Cow::Borrowed(&data),
+            },
+        )];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("mmap_open.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &path).unwrap();
+
+        for sequential in [false, true] {
+            let handle = open_mmap(path.to_str().unwrap(), sequential).unwrap();
+            handle.advise_random().unwrap();
+            assert_eq!(handle.names(), vec!["weight"]);
+            let weight = handle.get("weight").unwrap();
+            assert_eq!(weight.shape, vec![2, 4]);
+            assert_eq!(weight.dtype, crate::ffi::Dtype::U32);
+            assert_eq!(weight.data, data);
+            assert!(handle.get("missing").is_err());
+            handle.prefetch("weight").unwrap();
+            assert!(matches!(
+                handle.prefetch("missing"),
+                Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                    _
+                )))
+            ));
+        }
+
+        let broken = dir.join("mmap_open_broken.safetensors");
+        std::fs::write(&broken, [1, 2, 3]).unwrap();
+        assert!(open_mmap(broken.to_str().unwrap(), false).is_err());
+    }
+
+    #[test]
+    fn mmap_mut() {
+        let data = [1u8, 2, 3, 4];
+        let tensors = vec![
+            (
+                "a",
+                CowTensor {
+                    dtype: RDtype::U8,
+                    shape: vec![4],
+                    data: Cow::Borrowed(&data),
+                },
+            ),
+            (
+                "b",
+                CowTensor {
+                    dtype: RDtype::U16,
+                    shape: vec![2],
+                    data: Cow::Borrowed(&data),
+                },
+            ),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("mmap_mut.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, None, &path).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        let mut handle = open_file_mut(path.to_str().unwrap()).unwrap();
+        let b = handle.data_mut("b").unwrap();
+        assert_eq!(b, &data);
+        b.copy_from_slice(&[9, 0, 8, 0]);
+        handle.flush().unwrap();
+        assert!(matches!(
+            handle.data_mut("c"),
+            Err(CxxError::SafeTensorError(SafeTensorError::TensorNotFound(
+                _
+            )))
+        ));
+        drop(handle);
+
+        let buffer = std::fs::read(&path).unwrap();
+        assert_eq!(buffer.len() as u64, size);
+        let loaded = SafeTensors::deserialize(&buffer).unwrap();
+        assert_eq!(loaded.tensor("b").unwrap().data(), &[9, 0, 8, 0]);
+        assert_eq!(loaded.tensor("a").unwrap().data(), &data);
+    }
+}
@@ -0,0 +1,663 @@
+use crate::cast;
+use crate::error::CxxError;
+use crate::ffi::{Dtype, OwnedTensorView, SanitizedTensor, TensorStats, TensorView};
+use crate::{check_view, normalize_shape};
+use safetensors::SafeTensorError;
+
+/// Summary statistics of a tensor, decoded to `f64` whatever its dtype.
+/// NaNs are skipped for `min`, `max` and `mean` and only reported through
+/// `has_nan`. All three are NaN when no value is left to summarize.
+pub fn stats_f32(view: &TensorView) -> Result<TensorStats, CxxError> {
+    let values = cast::to_f64(view.dtype.try_into()?, view.data)?;
+
+    let mut stats = TensorStats {
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+        mean: 0.0,
+        has_nan: false,
+        has_inf: false,
+    };
+    let mut count = 0usize;
+    let mut sum = 0.0;
+    for value in values {
+        if value.is_nan() {
+            stats.has_nan = true;
+            continue;
+        }
+        stats.has_inf |= value.is_infinite();
+        stats.min = stats.min.min(value);
+        stats.max = stats.max.max(value);
+        sum += value;
+        count += 1;
+    }
+    if count == 0 {
+        stats.min = f64::NAN;
+        stats.max = f64::NAN;
+        stats.mean = f64::NAN;
+    } else {
+        stats.mean = sum / count as f64;
+    }
+    Ok(stats)
+}
+
+/// Reinterpret the data of `view` as elements of `T`, which must be what
+/// its dtype holds. No copy is made, so the bytes must be aligned for `T`.
+fn typed_slice<'v, T: bytemuck::Pod>(
+    expected: Dtype,
+    view: &TensorView<'v>,
+) -> Result<&'v [T], CxxError> {
+    if view.dtype != expected {
+        return Err(SafeTensorError::MisalignedSlice.into());
+    }
+    bytemuck::try_cast_slice(view.data).map_err(|_| SafeTensorError::MisalignedSlice.into())
+}
+
+/// The data of an F32 tensor as floats, borrowed from `view`. Fails with
+/// `MisalignedSlice` unless `view` is F32 and its data starts on a 4-byte
+/// boundary.
+pub fn as_f32_slice<'v>(view: &TensorView<'v>) -> Result<&'v [f32], CxxError> {
+    typed_slice(Dtype::F32, view)
+}
+
+/// Same as `as_f32_slice` for F64 tensors.
+pub fn as_f64_slice<'v>(view: &TensorView<'v>) -> Result<&'v [f64], CxxError> {
+    typed_slice(Dtype::F64, view)
+}
+
+/// Same as `as_f32_slice` for I32 tensors.
+pub fn as_i32_slice<'v>(view: &TensorView<'v>) -> Result<&'v [i32], CxxError> {
+    typed_slice(Dtype::I32, view)
+}
+
+/// Same as `as_f32_slice` for I64 tensors.
+pub fn as_i64_slice<'v>(view: &TensorView<'v>) -> Result<&'v [i64], CxxError> {
+    typed_slice(Dtype::I64, view)
+}
+
+/// Decode F8_E4M3 or F8_E5M2 bytes, one value per byte, to `f32`.
+pub fn fp8_to_f32(data: &[u8], dtype: Dtype) -> Result<Vec<f32>, CxxError> {
+    let dtype = dtype.try_into()?;
+    data.iter().map(|&b| cast::fp8_to_f32(dtype, b)).collect()
+}
+
+/// Decode F8_E8M0 scales, one per byte, to their `2^(e - 127)` value, for
+/// callers applying MX block scales themselves. `0xff` decodes to NaN.
+pub fn e8m0_to_scale_f32(data: &[u8]) -> Vec<f32> {
+    data.iter().map(|&b| cast::e8m0_to_f32(b)).collect()
+}
+
+/// Integer tensor upcast to `i64`, e.g. token ids or positions. U64 values
+/// must fit in an `i64`; float, boolean and sub-byte dtypes are rejected.
+pub fn to_i64(view: &TensorView) -> Result<Vec<i64>, CxxError> {
+    let dtype = view.dtype.try_into()?;
+    check_view(dtype, &normalize_shape(dtype, &view.shape), view.data.len())?;
+    cast::decode_i64(dtype, view.data)
+}
+
+/// Check that `view` holds exactly one element, e.g. shape `[]` or `[1]`,
+/// with as many bytes as its dtype calls for.
+fn check_scalar(view: &TensorView) -> Result<(), CxxError> {
+    let dtype = view.dtype.try_into()?;
+    let shape = normalize_shape(dtype, &view.shape);
+    // Validated first so the element count can't overflow.
+    check_view(dtype, &shape, view.data.len())?;
+    if shape.iter().product::<usize>() != 1 {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+    Ok(())
+}
+
+/// Value of a single-element tensor, e.g. a hyperparameter stored as a
+/// tensor, decoded to `f64` whatever its numeric dtype.
+pub fn scalar_f64(view: &TensorView) -> Result<f64, CxxError> {
+    check_scalar(view)?;
+    Ok(cast::to_f64(view.dtype.try_into()?, view.data)?[0])
+}
+
+/// Same as `scalar_f64` for integer tensors, without going through a float.
+pub fn scalar_i64(view: &TensorView) -> Result<i64, CxxError> {
+    check_scalar(view)?;
+    Ok(cast::decode_i64(view.dtype.try_into()?, view.data)?[0])
+}
+
+/// Exact values of an integer or boolean payload, U64 included, `None` for
+/// any other dtype.
+fn decode_i128(dtype: safetensors::Dtype, data: &[u8]) -> Option<Vec<i128>> {
+    match dtype {
+        safetensors::Dtype::BOOL => Some(data.iter().map(|&b| (b != 0) as i128).collect()),
+        safetensors::Dtype::U64 => Some(
+            data.chunks_exact(8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as i128)
+                .collect(),
+        ),
+        _ => cast::decode_i64(dtype, data)
+            .ok()
+            .map(|values| values.into_iter().map(i128::from).collect()),
+    }
+}
+
+/// Whether casting `view` to `target` would keep every value, to decide if
+/// a downcast is safe. Integer targets need every value to be a whole
+/// number within their range, `0` or `1` for BOOL. Float targets must hold
+/// integers exactly, and may round floats but not overflow them to
+/// infinity. Only BOOL, integer and F16/BF16/F32/F64 targets are supported.
+pub fn dtype_can_represent(view: &TensorView, target: Dtype) -> Result<bool, CxxError> {
+    let (from, to): (safetensors::Dtype, safetensors::Dtype) =
+        (view.dtype.try_into()?, target.try_into()?);
+    check_view(from, &normalize_shape(from, &view.shape), view.data.len())?;
+    let integers = decode_i128(from, view.data);
+
+    if cast::is_float(to) {
+        let values = cast::to_f64(from, view.data)?;
+        let back = cast::decode_f64(to, &cast::encode_f64(to, &values)?)?;
+        let kept = match integers {
+            Some(integers) => integers
+                .iter()
+                .zip(&back)
+                .all(|(&v, &b)| b.is_finite() && b as i128 == v),
+            None => values
+                .iter()
+                .zip(&back)
+                .all(|(v, b)| !v.is_finite() || b.is_finite()),
+        };
+        return Ok(kept);
+    }
+
+    let (min, max): (i128, i128) = match target {
+        Dtype::BOOL => (0, 1),
+        Dtype::U8 => (0, u8::MAX.into()),
+        Dtype::I8 => (i8::MIN.into(), i8::MAX.into()),
+        Dtype::U16 => (0, u16::MAX.into()),
+        Dtype::I16 => (i16::MIN.into(), i16::MAX.into()),
+        Dtype::U32 => (0, u32::MAX.into()),
+        Dtype::I32 => (i32::MIN.into(), i32::MAX.into()),
+        Dtype::U64 => (0, u64::MAX.into()),
+        Dtype::I64 => (i64::MIN.into(), i64::MAX.into()),
+        _ => return Err(CxxError::UnsupportedDtype(to)),
+    };
+    let fits = |v: i128| (min..=max).contains(&v);
+    let fit = match integers {
+        Some(integers) => integers.into_iter().all(fits),
+        None => cast::to_f64(from, view.data)?
+            .into_iter()
+            .all(|v| v.fract() == 0.0 && fits(v as i128)),
+    };
+    Ok(fit)
+}
+
+/// Cast a floating point tensor to a float dtype at most as wide, reusing
+/// its buffer and shrinking it afterwards so the extra memory is released.
+pub fn downcast_in_place(
+    mut view: OwnedTensorView,
+    target: Dtype,
+) -> Result<OwnedTensorView, CxxError> {
+    let (from, to): (safetensors::Dtype, safetensors::Dtype) =
+        (view.dtype.try_into()?, target.try_into()?);
+    if !cast::is_float(from) {
+        return Err(CxxError::UnsupportedDtype(from));
+    }
+    if !cast::is_float(to) || to.bitsize() > from.bitsize() {
+        return Err(CxxError::UnsupportedDtype(to));
+    }
+    check_view(from, &view.shape, view.data.len())?;
+
+    // Elements are converted by blocks, each written back over bytes that
+    // have already been decoded since the target is never wider.
+    const BLOCK: usize = 4096;
+    let (from_size, to_size) = (from.bitsize() / 8, to.bitsize() / 8);
+    let n = view.data.len() / from_size;
+    for start in (0..n).step_by(BLOCK) {
+        let end = (start + BLOCK).min(n);
+        let block = cast::cast_float(from, to, &view.data[start * from_size..end * from_size])?;
+        view.data[start * to_size..end * to_size].copy_from_slice(&block);
+    }
+    view.data.truncate(n * to_size);
+    view.data.shrink_to_fit();
+    view.dtype = target;
+    Ok(view)
+}
+
+/// Copy of a floating point tensor with NaNs set to `replace_nan` and
+/// infinities to `replace_inf`, negated for `-inf`, in the tensor's own
+/// dtype, e.g. to recover a checkpoint with a few corrupted half-precision
+/// weights. The substitutes are rounded to the dtype like any other value.
+pub fn sanitize_floats(
+    view: &TensorView,
+    replace_nan: f32,
+    replace_inf: f32,
+) -> Result<SanitizedTensor, CxxError> {
+    let dtype = view.dtype.try_into()?;
+    if !cast::is_float(dtype) {
+        return Err(CxxError::UnsupportedDtype(dtype));
+    }
+    check_view(dtype, &view.shape, view.data.len())?;
+
+    let (mut nan_count, mut inf_count) = (0, 0);
+    let mut values = cast::decode_f64(dtype, view.data)?;
+    for value in &mut values {
+        if value.is_nan() {
+            *value = replace_nan.into();
+            nan_count += 1;
+        } else if value.is_infinite() {
+            *value = f64::from(replace_inf).copysign(*value);
+            inf_count += 1;
+        }
+    }
+    Ok(SanitizedTensor {
+        tensor: OwnedTensorView {
+            shape: view.shape.clone(),
+            dtype: view.dtype,
+            data: cast::encode_f64(dtype, &values)?,
+        },
+        nan_count,
+        inf_count,
+    })
+}
+
+/// Dequantize int8 `weights` with one scale per index along `axis`:
+/// `out[i] = weights[i] * scales[index of i along axis]`.
+/// `scales` must be F32 or F16 and hold exactly `weights.shape[axis]` values.
+pub fn dequantize_i8(
+    weights: &TensorView,
+    scales: &TensorView,
+    axis: usize,
+) -> Result<Vec<f32>, CxxError> {
+    if weights.dtype != Dtype::I8 {
+        return Err(CxxError::UnsupportedDtype(weights.dtype.try_into()?));
+    }
+    if !matches!(scales.dtype, Dtype::F32 | Dtype::F16) {
+        return Err(CxxError::UnsupportedDtype(scales.dtype.try_into()?));
+    }
+    // Validated first so neither element count below can overflow.
+    check_view(safetensors::Dtype::I8, &weights.shape, weights.data.len())?;
+    let Some(&channels) = weights.shape.get(axis) else {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    };
+    let scales = cast::decode_f64(scales.dtype.try_into()?, scales.data)?;
+    if scales.len() != channels {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+
+    let inner: usize = weights.shape[axis + 1..].iter().product();
+    let values = weights
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, &w)| (w as i8 as f64 * scales[(i / inner) % channels]) as f32)
+        .collect();
+    Ok(values)
+}
+
+/// NumPy-style `allclose`: true if `|a - b| <= atol + rtol * |b|` for every
+/// element. The tensors may have different dtypes, e.g. a cast result and
+/// its source, but must have the same shape, otherwise they aren't close.
+/// NaNs are never close, infinities only to an infinity of the same sign.
+pub fn allclose(a: &TensorView, b: &TensorView, rtol: f64, atol: f64) -> Result<bool, CxxError> {
+    if a.shape != b.shape {
+        return Ok(false);
+    }
+    let a = cast::to_f64(a.dtype.try_into()?, a.data)?;
+    let b = cast::to_f64(b.dtype.try_into()?, b.data)?;
+    if a.len() != b.len() {
+        return Ok(false);
+    }
+    Ok(a.iter().zip(&b).all(|(&x, &y)| close(x, y, rtol, atol)))
+}
+
+/// Element-wise test behind `allclose`: `|x - y| <= atol + rtol * |y|` for
+/// finite values. Infinities must match exactly and NaN is never close,
+/// which the tolerance alone would get wrong since `rtol * inf` is inf.
+pub(crate) fn close(x: f64, y: f64, rtol: f64, atol: f64) -> bool {
+    if x.is_finite() && y.is_finite() {
+        (x - y).abs() <= atol + rtol * y.abs()
+    } else {
+        x == y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(dtype: Dtype, data: &[u8]) -> TensorView<'_> {
+        TensorView {
+            shape: vec![data.len() * 8 / dtype_bits(dtype)],
+            dtype,
+            data,
+            data_len: data.len(),
+        }
+    }
+
+    fn dtype_bits(dtype: Dtype) -> usize {
+        safetensors::Dtype::try_from(dtype).unwrap().bitsize()
+    }
+
+    #[test]
+    fn stats() {
+        let data: Vec<u8> = [1.0f32, -3.0, f32::NAN, 8.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let stats = stats_f32(&view(Dtype::F32, &data)).unwrap();
+        assert_eq!(stats.min, -3.0);
+        assert_eq!(stats.max, 8.0);
+        assert_eq!(stats.mean, 2.0);
+        assert!(stats.has_nan);
+        assert!(!stats.has_inf);
+
+        let data: Vec<u8> = [-2i16, 4].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let stats = stats_f32(&view(Dtype::I16, &data)).unwrap();
+        assert_eq!((stats.min, stats.max, stats.mean), (-2.0, 4.0, 1.0));
+
+        let stats = stats_f32(&view(Dtype::F32, &[])).unwrap();
+        assert!(stats.mean.is_nan());
+    }
+
+    #[test]
+    fn fp8() {
+        assert_eq!(
+            fp8_to_f32(&[0x38, 0xc0, 0x7e], Dtype::F8_E4M3).unwrap(),
+            vec![1.0, -2.0, 448.0]
+        );
+        let decoded = fp8_to_f32(&[0x3c, 0x7c], Dtype::F8_E5M2).unwrap();
+        assert_eq!(decoded, vec![1.0, f32::INFINITY]);
+        assert!(fp8_to_f32(&[0], Dtype::U8).is_err());
+
+        let stats = stats_f32(&view(Dtype::F8_E4M3, &[0x38, 0x40, 0x7f])).unwrap();
+        assert_eq!((stats.min, stats.max), (1.0, 2.0));
+        assert!(stats.has_nan);
+    }
+
+    #[test]
+    fn e8m0_scales() {
+        let scales = e8m0_to_scale_f32(&[127, 128, 120, 0xff]);
+        assert_eq!(scales[..3], [1.0, 2.0, 2f32.powi(-7)]);
+        assert!(scales[3].is_nan());
+        assert!(e8m0_to_scale_f32(&[]).is_empty());
+    }
+
+    #[test]
+    fn integers_to_i64() {
+        let data: Vec<u8> = [-1i8, 2, -128].iter().map(|&v| v as u8).collect();
+        assert_eq!(to_i64(&view(Dtype::I8, &data)).unwrap(), vec![-1, 2, -128]);
+        let data: Vec<u8> = [7u32, u32::MAX]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(
+            to_i64(&view(Dtype::U32, &data)).unwrap(),
+            vec![7, u32::MAX as i64]
+        );
+        let data: Vec<u8> = [5u64, i64::MAX as u64]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(to_i64(&view(Dtype::U64, &data)).unwrap(), vec![5, i64::MAX]);
+
+        let data = u64::MAX.to_le_bytes();
+        assert!(matches!(
+            to_i64(&view(Dtype::U64, &data)),
+            Err(CxxError::OutOfRange(value)) if value == u64::MAX.to_string()
+        ));
+        assert!(matches!(
+            to_i64(&view(Dtype::F32, &[0; 4])),
+            Err(CxxError::UnsupportedDtype(_))
+        ));
+        assert!(to_i64(&view(Dtype::BOOL, &[1])).is_err());
+        let truncated = TensorView {
+            shape: vec![2],
+            ..view(Dtype::I32, &[0; 7])
+        };
+        assert!(to_i64(&truncated).is_err());
+    }
+
+    #[test]
+    fn scalars() {
+        let scalar = |dtype, shape, data| TensorView {
+            shape,
+            dtype,
+            data,
+            data_len: data.len(),
+        };
+        let lr = 0.5f32.to_le_bytes();
+        assert_eq!(scalar_f64(&scalar(Dtype::F32, vec![], &lr)).unwrap(), 0.5);
+        let steps = (-7i64).to_le_bytes();
+        assert_eq!(
+            scalar_i64(&scalar(Dtype::I64, vec![1], &steps)).unwrap(),
+            -7
+        );
+        assert_eq!(
+            scalar_f64(&scalar(Dtype::I64, vec![1], &steps)).unwrap(),
+            -7.0
+        );
+        assert!(matches!(
+            scalar_i64(&scalar(Dtype::F32, vec![], &lr)),
+            Err(CxxError::UnsupportedDtype(_))
+        ));
+
+        let pair = [1u8, 2];
+        assert!(matches!(
+            scalar_f64(&scalar(Dtype::U8, vec![2], &pair)),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::TensorInvalidInfo
+            ))
+        ));
+        assert!(matches!(
+            scalar_i64(&scalar(Dtype::U8, vec![], &pair)),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::InvalidTensorView(..)
+            ))
+        ));
+        assert!(matches!(
+            scalar_f64(&scalar(Dtype::U8, vec![usize::MAX, 2], &pair)),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::ValidationOverflow
+            ))
+        ));
+    }
+
+    #[test]
+    fn representable() {
+        let ints: Vec<u8> = [-3i32, 300, 1 << 24 | 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let ints = view(Dtype::I32, &ints);
+        assert!(dtype_can_represent(&ints, Dtype::I64).unwrap());
+        assert!(dtype_can_represent(&ints, Dtype::F64).unwrap());
+        // 2^24 + 1 has no exact F32 representation.
+        assert!(!dtype_can_represent(&ints, Dtype::F32).unwrap());
+        assert!(!dtype_can_represent(&ints, Dtype::I16).unwrap());
+        assert!(!dtype_can_represent(&ints, Dtype::U32).unwrap());
+
+        let big = u64::MAX.to_le_bytes();
+        assert!(dtype_can_represent(&view(Dtype::U64, &big), Dtype::U64).unwrap());
+        assert!(!dtype_can_represent(&view(Dtype::U64, &big), Dtype::I64).unwrap());
+
+        let floats: Vec<u8> = [1.5f32, 70000.0, f32::NAN]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let floats = view(Dtype::F32, &floats);
+        assert!(dtype_can_represent(&floats, Dtype::BF16).unwrap());
+        // 70000 overflows F16, whose max is 65504.
+        assert!(!dtype_can_represent(&floats, Dtype::F16).unwrap());
+        assert!(!dtype_can_represent(&floats, Dtype::I32).unwrap());
+
+        let whole: Vec<u8> = [0.0f32, 1.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert!(dtype_can_represent(&view(Dtype::F32, &whole), Dtype::BOOL).unwrap());
+        assert!(dtype_can_represent(&view(Dtype::BOOL, &[1, 0]), Dtype::I8).unwrap());
+
+        assert!(matches!(
+            dtype_can_represent(&floats, Dtype::F8_E4M3),
+            Err(CxxError::UnsupportedDtype(_))
+        ));
+    }
+
+    #[test]
+    fn sanitize() {
+        use half::f16;
+        let data: Vec<u8> = [1.5, f32::NAN, f32::INFINITY, f32::NEG_INFINITY]
+            .into_iter()
+            .flat_map(|v| f16::from_f32(v).to_le_bytes())
+            .collect();
+        let sanitized = sanitize_floats(&view(Dtype::F16, &data), 0.0, 65504.0).unwrap();
+        assert_eq!((sanitized.nan_count, sanitized.inf_count), (1, 2));
+        assert_eq!(sanitized.tensor.dtype, Dtype::F16);
+        let values: Vec<f32> = sanitized
+            .tensor
+            .data
+            .chunks_exact(2)
+            .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32())
+            .collect();
+        assert_eq!(values, [1.5, 0.0, 65504.0, -65504.0]);
+
+        let clean = 2.0f32.to_le_bytes();
+        let sanitized = sanitize_floats(&view(Dtype::F32, &clean), 0.0, 0.0).unwrap();
+        assert_eq!((sanitized.nan_count, sanitized.inf_count), (0, 0));
+        assert_eq!(sanitized.tensor.data, clean);
+
+        assert!(matches!(
+            sanitize_floats(&view(Dtype::I32, &[0; 4]), 0.0, 0.0),
+            Err(CxxError::UnsupportedDtype(_))
+        ));
+    }
+
+    #[test]
+    fn downcast() {
+        let values: Vec<f32> = (0..10_000).map(|i| i as f32 / 8.0).collect();
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let view = OwnedTensorView {
+            shape: vec![100, 100],
+            dtype: Dtype::F32,
+            data: data.clone(),
+        };
+
+        let half = downcast_in_place(view.clone(), Dtype::BF16).unwrap();
+        assert_eq!(half.dtype, Dtype::BF16);
+        assert_eq!(half.shape, vec![100, 100]);
+        assert_eq!(half.data.len(), 20_000);
+        assert_eq!(
+            half.data,
+            cast::cast_float(safetensors::Dtype::F32, safetensors::Dtype::BF16, &data).unwrap()
+        );
+
+        assert!(downcast_in_place(half, Dtype::F64).is_err());
+        let ints = OwnedTensorView {
+            dtype: Dtype::I32,
+            ..view
+        };
+        assert!(downcast_in_place(ints, Dtype::F16).is_err());
+    }
+
+    #[test]
+    fn typed_slices() {
+        let values = [1.5f64, -2.0, 4.0];
+        let data: &[u8] = bytemuck::cast_slice(&values);
+        assert_eq!(as_f64_slice(&view(Dtype::F64, data)).unwrap(), &values);
+        assert_eq!(
+            as_f32_slice(&view(Dtype::F32, &data[..8])).unwrap().len(),
+            2
+        );
+        assert!(matches!(
+            as_f32_slice(&view(Dtype::F32, &data[1..5])),
+            Err(CxxError::SafeTensorError(SafeTensorError::MisalignedSlice))
+        ));
+        // Aligned bytes of another dtype are rejected all the same.
+        for dtype in [Dtype::F32, Dtype::I64, Dtype::U8] {
+            assert!(matches!(
+                as_i32_slice(&view(dtype, data)),
+                Err(CxxError::SafeTensorError(SafeTensorError::MisalignedSlice))
+            ));
+        }
+        assert!(matches!(
+            as_f64_slice(&view(Dtype::I64, data)),
+            Err(CxxError::SafeTensorError(SafeTensorError::MisalignedSlice))
+        ));
+    }
+
+    #[test]
+    fn dequantize() {
+        let weights: Vec<u8> = [1i8, -2, 3, 4, -5, 6].iter().map(|&w| w as u8).collect();
+        let weights = TensorView {
+            shape: vec![2, 3],
+            dtype: Dtype::I8,
+            data: &weights,
+            data_len: 6,
+        };
+        let rows: Vec<u8> = [0.5f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let rows = view(Dtype::F32, &rows);
+        assert_eq!(
+            dequantize_i8(&weights, &rows, 0).unwrap(),
+            vec![0.5, -1.0, 1.5, 8.0, -10.0, 12.0]
+        );
+
+        let cols: Vec<u8> = [1.0f32, 0.0, -1.0]
+            .iter()
+            .flat_map(|&v| half::f16::from_f32(v).to_le_bytes())
+            .collect();
+        let cols = view(Dtype::F16, &cols);
+        assert_eq!(
+            dequantize_i8(&weights, &cols, 1).unwrap(),
+            vec![1.0, 0.0, -3.0, 4.0, 0.0, -6.0]
+        );
+
+        assert!(dequantize_i8(&weights, &rows, 1).is_err());
+        assert!(dequantize_i8(&weights, &rows, 2).is_err());
+        assert!(dequantize_i8(&rows, &rows, 0).is_err());
+        let hostile = TensorView {
+            shape: vec![usize::MAX, 2, 3],
+            ..weights
+        };
+        assert!(matches!(
+            dequantize_i8(&hostile, &rows, 0),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::ValidationOverflow
+            ))
+        ));
+    }
+
+    #[test]
+    fn close() {
+        let source: Vec<u8> = [1.0f32, 100.0, -0.001, f32::INFINITY]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let source = view(Dtype::F32, &source);
+        let casted = crate::cast::cast_float(
+            safetensors::Dtype::F32,
+            safetensors::Dtype::BF16,
+            source.data,
+        )
+        .unwrap();
+        let casted = view(Dtype::BF16, &casted);
+
+        assert!(!allclose(&casted, &source, 0.0, 0.0).unwrap());
+        assert!(allclose(&casted, &source, 1e-2, 1e-5).unwrap());
+        assert!(allclose(&source, &source, 0.0, 0.0).unwrap());
+
+        let nan = f32::NAN.to_le_bytes();
+        let nan = view(Dtype::F32, &nan);
+        assert!(!allclose(&nan, &nan, 1.0, 1.0).unwrap());
+
+        let other_shape = TensorView {
+            shape: vec![2, 2],
+            ..source.clone()
+        };
+        assert!(!allclose(&other_shape, &source, 1.0, 1.0).unwrap());
+    }
+
+    #[test]
+    fn close_non_finite() {
+        let pair = |x: f32, y: f32| {
+            let (x, y) = (x.to_le_bytes(), y.to_le_bytes());
+            allclose(&view(Dtype::F32, &x), &view(Dtype::F32, &y), 1e-5, 1e-8).unwrap()
+        };
+        assert!(!pair(1.0, f32::INFINITY));
+        assert!(!pair(f32::INFINITY, 1.0));
+        assert!(!pair(f32::INFINITY, f32::NEG_INFINITY));
+        assert!(pair(f32::NEG_INFINITY, f32::NEG_INFINITY));
+        assert!(!pair(f32::NAN, f32::NAN));
+        assert!(!pair(f32::MAX, f32::INFINITY));
+    }
+}
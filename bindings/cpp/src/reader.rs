@@ -0,0 +1,127 @@
+//! Loading from a source other than a file or an in-memory buffer, e.g. a
+//! decryption stream or an object store. cxx cannot hand a C++ callback to
+//! Rust, so the loader says which bytes it needs next and C++ feeds them, see
+//! `deserialize_from_reader` in safetensors.hpp.
+use crate::error::CxxError;
+use crate::ffi::PairStrOwnedTensorView;
+use crate::{data_len, deserialize_owned, header_len, parse_header, N_LEN};
+use safetensors::SafeTensorError;
+
+/// Handle returned by `new_reader_loader`.
+pub struct ReaderLoader {
+    buffer: Vec<u8>,
+    needed: usize,
+    header_read: bool,
+}
+
+/// Start loading a file from scratch: the first bytes asked for are its
+/// length prefix.
+pub fn new_reader_loader() -> Box<ReaderLoader> {
+    Box::new(ReaderLoader {
+        buffer: Vec::new(),
+        needed: N_LEN,
+        header_read: false,
+    })
+}
+
+impl ReaderLoader {
+    /// Position in the file of the next bytes to `feed`.
+    pub fn offset(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// How many bytes are still wanted from `offset` on, as far as the file
+    /// read so far tells. Zero once the whole file is in.
+    pub fn remaining(&self) -> usize {
+        self.needed - self.buffer.len()
+    }
+
+    /// Append the next `bytes` of the file, at most `remaining` of them. Once
+    /// the length prefix, then the header, are complete, `remaining` grows to
+    /// cover what comes next.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), CxxError> {
+        if bytes.len() > self.remaining() {
+            return Err(CxxError::LimitExceeded(
+                "read",
+                bytes.len() as u64,
+                self.remaining(),
+            ));
+        }
+        self.buffer.extend_from_slice(bytes);
+
+        if self.needed == N_LEN && self.buffer.len() == N_LEN {
+            let prefix = self.buffer[..N_LEN].try_into().unwrap();
+            self.needed += header_len(prefix)?;
+        }
+        if !self.header_read && self.needed > N_LEN && self.buffer.len() == self.needed {
+            let metadata = parse_header(&self.buffer[N_LEN..])?;
+            self.needed += data_len(&metadata);
+            self.header_read = true;
+        }
+        Ok(())
+    }
+
+    /// Tensors of the file fed so far, validated like `deserialize` does.
+    /// Fails if bytes are still `remaining`. The bytes are handed over, so
+    /// the loader can't be used afterwards.
+    pub fn finish(&mut self) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+        if self.remaining() != 0 {
+            return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+        }
+        let buffer = std::mem::take(&mut self.buffer);
+        deserialize_owned(&buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CowTensor;
+    use safetensors::Dtype as RDtype;
+    use std::borrow::Cow;
+
+    /// Drive `loader` the way the C++ helper does, reading at most `chunk`
+    /// bytes at a time from `source`.
+    fn load(source: &[u8], chunk: usize) -> Result<Vec<PairStrOwnedTensorView>, CxxError> {
+        let mut loader = new_reader_loader();
+        while loader.remaining() != 0 {
+            let start = loader.offset();
+            let end = (start + loader.remaining().min(chunk)).min(source.len());
+            if start == end {
+                return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+            }
+            loader.feed(&source[start..end])?;
+        }
+        loader.finish()
+    }
+
+    #[test]
+    fn chunked_reads() {
+        let weight: Vec<u8> = (0..6u16).flat_map(|v| v.to_le_bytes()).collect();
+        let tensors = vec![(
+            "weight",
+            CowTensor {
+                dtype: RDtype::U16,
+                shape: vec![2, 3],
+                data: Cow::Borrowed(&weight),
+            },
+        )];
+        let file = safetensors::serialize(tensors, None).unwrap();
+
+        for chunk in [1, 5, 64, usize::MAX] {
+            let loaded = load(&file, chunk).unwrap();
+            assert_eq!(loaded.len(), 1);
+            assert_eq!(loaded[0].key, "weight");
+            assert_eq!(loaded[0].value.shape, vec![2, 3]);
+            assert_eq!(loaded[0].value.data, weight);
+        }
+        assert!(load(&file[..file.len() - 1], 64).is_err());
+
+        let mut loader = new_reader_loader();
+        assert!(matches!(
+            loader.feed(&file[..N_LEN + 1]),
+            Err(CxxError::LimitExceeded("read", 9, 8))
+        ));
+        assert!(loader.finish().is_err());
+    }
+}
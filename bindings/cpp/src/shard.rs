@@ -0,0 +1,582 @@
+//! Models split across several files, tied together by a Hugging Face style
+//! `model.safetensors.index.json`.
+use crate::error::CxxError;
+use crate::file::{read_header, PendingFiles};
+use crate::header::{merge_metadata, write_header};
+use crate::{convert_to_hashmap_string, metadata_pairs, CowTensor, N_LEN};
+use safetensors::tensor::{Metadata, TensorInfo};
+use safetensors::SafeTensorError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Content of an index file: `total_size` is the byte size of all tensors,
+/// `weight_map` the file name of the shard holding each tensor.
+pub(crate) fn index_json(weight_map: &BTreeMap<String, String>, total_size: usize) -> Value {
+    json!({
+        "metadata": { "total_size": total_size },
+        "weight_map": weight_map,
+    })
+}
+
+/// Write the index of shards produced by another tool, reading only their
+/// headers. Shards are referred to by file name, so the index is expected
+/// to sit next to them. Fails if a tensor is found in two shards.
+pub fn write_index(shard_paths: Vec<String>, out_index_path: &str) -> Result<(), CxxError> {
+    let mut weight_map = BTreeMap::new();
+    let mut total_size = 0;
+    for path in &shard_paths {
+        let (_, _, metadata) = read_header(path)?;
+        let file_name = Path::new(path)
+            .file_name()
+            .map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+        for (name, info) in metadata.tensors() {
+            total_size += info.data_offsets.1 - info.data_offsets.0;
+            if weight_map.insert(name.clone(), file_name.clone()).is_some() {
+                return Err(CxxError::DuplicateName(name));
+            }
+        }
+    }
+
+    let index = serde_json::to_string_pretty(&index_json(&weight_map, total_size))
+        .map_err(safetensors::SafeTensorError::JsonError)?;
+    std::fs::write(out_index_path, index)?;
+    Ok(())
+}
+
+/// The parts of an index file `verify_sharded` checks.
+#[derive(Deserialize)]
+struct Index {
+    #[serde(default)]
+    metadata: IndexMetadata,
+    weight_map: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+struct IndexMetadata {
+    total_size: Option<usize>,
+}
+
+/// Check the shards listed in the index at `index_path` against it, reading
+/// only their headers and sizes. Returns one line per discrepancy, empty if
+/// the shards match: tensors missing from their shard, tensors a shard holds
+/// but the index doesn't assign to it, shards whose size doesn't match their
+/// header, e.g. truncated downloads, and a `total_size` differing from the
+/// tensors' actual byte size. Shards are looked up next to the index. Fails
+/// only if the index itself can't be read.
+pub fn verify_sharded(index_path: &str) -> Result<Vec<String>, CxxError> {
+    let index: Index =
+        serde_json::from_slice(&std::fs::read(index_path)?).map_err(SafeTensorError::JsonError)?;
+    let dir = Path::new(index_path).parent().unwrap_or(Path::new(""));
+
+    let mut shards: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, shard) in &index.weight_map {
+        shards.entry(shard).or_default().insert(name);
+    }
+
+    let mut diffs = Vec::new();
+    let mut total_size = 0;
+    for (shard, expected) in shards {
+        let (file, n, metadata) = match read_header(dir.join(shard).to_str().unwrap_or(shard)) {
+            Ok(header) => header,
+            Err(err) => {
+                diffs.push(format!("{shard}: {err}"));
+                continue;
+            }
+        };
+        let tensors = metadata.tensors();
+        for &name in &expected {
+            match tensors.get(name) {
+                Some(info) => total_size += info.data_offsets.1 - info.data_offsets.0,
+                None => diffs.push(format!("{shard}: missing {name}")),
+            }
+        }
+        let mut extras: Vec<_> = tensors
+            .keys()
+            .filter(|name| !expected.contains(name.as_str()))
+            .collect();
+        extras.sort();
+        diffs.extend(
+            extras
+                .into_iter()
+                .map(|name| format!("{shard}: unexpected {name}")),
+        );
+
+        let payload_len = tensors
+            .values()
+            .map(|info| info.data_offsets.1)
+            .max()
+            .unwrap_or(0);
+        let expected_len = (N_LEN + n + payload_len) as u64;
+        let actual_len = file.metadata()?.len();
+        if actual_len != expected_len {
+            diffs.push(format!(
+                "{shard}: size {actual_len} bytes, header calls for {expected_len}"
+            ));
+        }
+    }
+    if let Some(expected) = index.metadata.total_size {
+        if expected != total_size {
+            diffs.push(format!(
+                "total_size {expected} in index, tensors hold {total_size}"
+            ));
+        }
+    }
+    Ok(diffs)
+}
+
+/// Redistribute the tensors of `in_path` across exactly `num_shards` files
+/// named `{out_prefix}-00001-of-0000N.safetensors`, and write their index to
+/// `{out_prefix}.safetensors.index.json`. `in_path` is either a single file
+/// or the index of an already sharded model, e.g. to match a deployment
+/// expecting one shard per GPU. Tensors are spread largest first onto the
+/// lightest shard, which balances bytes roughly evenly; with more shards
+/// than tensors, the extra shards are valid files holding none. The
+/// `__metadata__` of the source, or of its first shard, is copied to every
+/// shard. Returns the paths of the shards written.
+///
+/// Shards and index are written to temporary files first and only moved
+/// into place once all of them are complete, so resharding a model in place,
+/// e.g. with `out_prefix` naming the source shards, is safe, and a failure
+/// leaves existing files untouched.
+pub fn reshard(
+    in_path: &str,
+    num_shards: usize,
+    out_prefix: &str,
+) -> Result<Vec<String>, CxxError> {
+    if num_shards == 0 {
+        return Err(SafeTensorError::TensorInvalidInfo.into());
+    }
+    let source_paths: Vec<String> = if in_path.ends_with(".json") {
+        let index: Index =
+            serde_json::from_slice(&std::fs::read(in_path)?).map_err(SafeTensorError::JsonError)?;
+        let dir = Path::new(in_path).parent().unwrap_or(Path::new(""));
+        let shards: BTreeSet<String> = index.weight_map.into_values().collect();
+        shards
+            .into_iter()
+            .map(|shard| dir.join(shard).to_string_lossy().into_owned())
+            .collect()
+    } else {
+        vec![in_path.to_string()]
+    };
+
+    let mut sources = Vec::with_capacity(source_paths.len());
+    let mut tensors = Vec::new();
+    let mut names = HashSet::new();
+    for path in &source_paths {
+        let (file, n, metadata) = read_header(path)?;
+        for (name, info) in metadata.tensors() {
+            if !names.insert(name.clone()) {
+                return Err(CxxError::DuplicateName(name));
+            }
+            tensors.push((name, sources.len(), info.clone()));
+        }
+        sources.push((file, n, metadata));
+    }
+    let data_info = sources
+        .first()
+        .and_then(|(_, _, metadata)| metadata.metadata().clone());
+
+    // Largest first onto the lightest shard, ties broken by name and index.
+    let size = |info: &TensorInfo| info.data_offsets.1 - info.data_offsets.0;
+    tensors.sort_by(|(lname, _, left), (rname, _, right)| {
+        size(right).cmp(&size(left)).then(lname.cmp(rname))
+    });
+    let mut shards = vec![(0usize, Vec::new()); num_shards];
+    for tensor in tensors {
+        let (bytes, assigned) = shards
+            .iter_mut()
+            .min_by_key(|(bytes, _)| *bytes)
+            .expect("num_shards is not zero");
+        *bytes += size(&tensor.2);
+        assigned.push(tensor);
+    }
+
+    let mut pending = PendingFiles::default();
+    let mut weight_map = BTreeMap::new();
+    let mut total_size = 0;
+    let mut out_paths = Vec::with_capacity(num_shards);
+    for (i, (bytes, assigned)) in shards.into_iter().enumerate() {
+        let out_path = format!("{out_prefix}-{:05}-of-{num_shards:05}.safetensors", i + 1);
+        let file_name = Path::new(&out_path).file_name().map_or_else(
+            || out_path.clone(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let mut shard = Vec::with_capacity(assigned.len());
+        for (name, source, info) in assigned {
+            let (file, n, _) = &mut sources[source];
+            let (start, end) = info.data_offsets;
+            let mut data = vec![0u8; end - start];
+            file.seek(SeekFrom::Start((N_LEN + *n + start) as u64))?;
+            file.read_exact(&mut data)?;
+            weight_map.insert(name.clone(), file_name.clone());
+            let tensor = CowTensor {
+                dtype: info.dtype,
+                shape: info.shape,
+                data: Cow::Owned(data),
+            };
+            shard.push((name, tensor));
+        }
+        total_size += bytes;
+        let temp = pending.path(&out_path);
+        safetensors::tensor::serialize_to_file(shard, data_info.clone(), &temp)?;
+        out_paths.push(out_path);
+    }
+
+    let index = serde_json::to_string_pretty(&index_json(&weight_map, total_size))
+        .map_err(SafeTensorError::JsonError)?;
+    std::fs::write(
+        pending.path(&format!("{out_prefix}.safetensors.index.json")),
+        index,
+    )?;
+    pending.commit()?;
+    Ok(out_paths)
+}
+
+/// Assemble `paths` into a single file at `out_path`, keeping their tensors
+/// in the order given. Only headers are held in memory, each payload being
+/// streamed straight to the output. `__metadata__` entries are merged
+/// through `merge_metadata` with `prefer_first`, so on a key set by several
+/// shards the first one in `paths` wins. Name collisions across shards, shards
+/// shorter than their header calls for and an `out_path` naming one of the
+/// shards are reported before anything is written. The output is written
+/// under a temporary name and moved into place once complete.
+pub fn concat_files_to_file(paths: Vec<String>, out_path: &str) -> Result<(), CxxError> {
+    if let Ok(out) = std::fs::canonicalize(out_path) {
+        for path in &paths {
+            if std::fs::canonicalize(path).is_ok_and(|path| path == out) {
+                return Err(CxxError::OutputIsInput(out_path.to_string()));
+            }
+        }
+    }
+
+    let mut shards = Vec::with_capacity(paths.len());
+    let mut names = HashSet::new();
+    let mut tensors = Vec::new();
+    let mut data_info = Vec::new();
+    let mut offset = 0;
+    for path in &paths {
+        let (file, n, metadata) = read_header(path)?;
+        let mut payload_len = 0;
+        for name in metadata.offset_keys() {
+            let info = metadata
+                .info(&name)
+                .expect("offset_keys come from the metadata");
+            let (start, end) = info.data_offsets;
+            payload_len = end;
+            if !names.insert(name.clone()) {
+                return Err(CxxError::DuplicateName(name));
+            }
+            tensors.push((
+                name,
+                TensorInfo {
+                    dtype: info.dtype,
+                    shape: info.shape.clone(),
+                    data_offsets: (offset + start, offset + end),
+                },
+            ));
+        }
+        data_info = merge_metadata(data_info, metadata_pairs(&metadata), true);
+        if file.metadata()?.len() < (N_LEN + n + payload_len) as u64 {
+            return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+        }
+        offset += payload_len;
+        shards.push((file, payload_len));
+    }
+    let data_info = convert_to_hashmap_string(data_info);
+    let header = write_header(&Metadata::new(data_info, tensors)?, &[])?;
+
+    let mut pending = PendingFiles::default();
+    let mut out = BufWriter::new(File::create(pending.path(out_path))?);
+    out.write_all(&header)?;
+    for (file, payload_len) in shards {
+        let copied = std::io::copy(&mut file.take(payload_len as u64), &mut out)?;
+        if copied != payload_len as u64 {
+            return Err(SafeTensorError::MetadataIncompleteBuffer.into());
+        }
+    }
+    out.flush()?;
+    drop(out);
+    pending.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use safetensors::Dtype as RDtype;
+    use std::collections::HashMap;
+
+    fn write_shard(path: &Path, names: &[&str]) {
+        let tensors: Vec<_> = names
+            .iter()
+            .map(|&name| {
+                let tensor = CowTensor {
+                    dtype: RDtype::F32,
+                    shape: vec![2],
+                    data: Cow::Owned(name.bytes().cycle().take(8).collect()),
+                };
+                (name, tensor)
+            })
+            .collect();
+        safetensors::tensor::serialize_to_file(tensors, None, path).unwrap();
+    }
+
+    #[test]
+    fn index_from_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let first = dir.join("write_index-00001-of-00002.safetensors");
+        let second = dir.join("write_index-00002-of-00002.safetensors");
+        write_shard(&first, &["a", "b"]);
+        write_shard(&second, &["c"]);
+        let shards = vec![
+            first.to_str().unwrap().to_string(),
+            second.to_str().unwrap().to_string(),
+        ];
+
+        let index_path = dir.join("write_index.safetensors.index.json");
+        write_index(shards.clone(), index_path.to_str().unwrap()).unwrap();
+        let index: Value = serde_json::from_slice(&std::fs::read(&index_path).unwrap()).unwrap();
+        assert_eq!(index["metadata"]["total_size"], 24);
+        assert_eq!(
+            index["weight_map"]["b"],
+            "write_index-00001-of-00002.safetensors"
+        );
+        assert_eq!(
+            index["weight_map"]["c"],
+            "write_index-00002-of-00002.safetensors"
+        );
+
+        let duplicated = vec![shards[0].clone(), shards[0].clone()];
+        assert!(matches!(
+            write_index(duplicated, index_path.to_str().unwrap()),
+            Err(CxxError::DuplicateName(_))
+        ));
+    }
+
+    #[test]
+    fn concat_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let first = dir.join("concat_shards-00001-of-00002.safetensors");
+        let second = dir.join("concat_shards-00002-of-00002.safetensors");
+        write_shard(&first, &["a", "b"]);
+        let tensors = vec![(
+            "c",
+            CowTensor {
+                dtype: RDtype::U8,
+                shape: vec![3],
+                data: Cow::Owned(vec![1, 2, 3]),
+            },
+        )];
+        let data_info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        safetensors::tensor::serialize_to_file(tensors, Some(data_info), &second).unwrap();
+        let shards = vec![
+            first.to_str().unwrap().to_string(),
+            second.to_str().unwrap().to_string(),
+        ];
+
+        let out_path = dir.join("concat_shards.safetensors");
+        concat_files_to_file(shards.clone(), out_path.to_str().unwrap()).unwrap();
+        let buffer = std::fs::read(&out_path).unwrap();
+        let merged = safetensors::SafeTensors::deserialize(&buffer).unwrap();
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged.tensor("a").unwrap().data(), b"aaaaaaaa");
+        assert_eq!(merged.tensor("b").unwrap().data(), b"bbbbbbbb");
+        assert_eq!(merged.tensor("c").unwrap().data(), &[1, 2, 3]);
+        let (_, metadata) = safetensors::SafeTensors::read_metadata(&buffer).unwrap();
+        assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+        assert_eq!(metadata.offset_keys(), vec!["a", "b", "c"]);
+
+        std::fs::remove_file(&out_path).unwrap();
+        let duplicated = vec![shards[0].clone(), shards[0].clone()];
+        assert!(matches!(
+            concat_files_to_file(duplicated, out_path.to_str().unwrap()),
+            Err(CxxError::DuplicateName(_))
+        ));
+        assert!(!out_path.exists());
+
+        // A short shard leaves an existing output alone.
+        std::fs::write(&out_path, b"previous").unwrap();
+        let buffer = std::fs::read(&second).unwrap();
+        let short = dir.join("concat_shards-short.safetensors");
+        std::fs::write(&short, &buffer[..buffer.len() - 1]).unwrap();
+        let with_short = vec![shards[0].clone(), short.to_str().unwrap().to_string()];
+        assert!(matches!(
+            concat_files_to_file(with_short, out_path.to_str().unwrap()),
+            Err(CxxError::SafeTensorError(
+                SafeTensorError::MetadataIncompleteBuffer
+            ))
+        ));
+        assert_eq!(std::fs::read(&out_path).unwrap(), b"previous");
+
+        let before = std::fs::read(&first).unwrap();
+        assert!(matches!(
+            concat_files_to_file(shards.clone(), &shards[0]),
+            Err(CxxError::OutputIsInput(path)) if path == shards[0]
+        ));
+        assert_eq!(std::fs::read(&first).unwrap(), before);
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 4);
+
+        // On a `__metadata__` clash, the first shard listed wins.
+        let tensors = vec![(
+            "d",
+            CowTensor {
+                dtype: RDtype::U8,
+                shape: vec![1],
+                data: Cow::Owned(vec![4]),
+            },
+        )];
+        let data_info = HashMap::from([
+            ("format".to_string(), "np".to_string()),
+            ("license".to_string(), "mit".to_string()),
+        ]);
+        let third = dir.join("concat_shards-third.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, Some(data_info), &third).unwrap();
+        let third = third.to_str().unwrap().to_string();
+        let clash_path = dir.join("concat_shards-clash.safetensors");
+        let clash_path = clash_path.to_str().unwrap();
+        let metadata_of = |paths: Vec<String>| {
+            concat_files_to_file(paths, clash_path).unwrap();
+            let buffer = std::fs::read(clash_path).unwrap();
+            let (_, metadata) = safetensors::SafeTensors::read_metadata(&buffer).unwrap();
+            metadata.metadata().clone().unwrap()
+        };
+        let merged = metadata_of(vec![shards[1].clone(), third.clone()]);
+        assert_eq!(merged["format"], "pt");
+        assert_eq!(merged["license"], "mit");
+        let merged = metadata_of(vec![third, shards[1].clone()]);
+        assert_eq!(merged["format"], "np");
+    }
+
+    #[test]
+    fn verify_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let first = dir.join("verify_sharded-00001-of-00002.safetensors");
+        let second = dir.join("verify_sharded-00002-of-00002.safetensors");
+        write_shard(&first, &["a", "b"]);
+        write_shard(&second, &["c"]);
+        let shards = vec![
+            first.to_str().unwrap().to_string(),
+            second.to_str().unwrap().to_string(),
+        ];
+        let index_path = dir.join("verify_sharded.safetensors.index.json");
+        let index_path = index_path.to_str().unwrap();
+        write_index(shards, index_path).unwrap();
+        assert!(verify_sharded(index_path).unwrap().is_empty());
+
+        // A shard missing a tensor and holding one the index doesn't know.
+        write_shard(&second, &["d"]);
+        assert_eq!(
+            verify_sharded(index_path).unwrap(),
+            vec![
+                "verify_sharded-00002-of-00002.safetensors: missing c",
+                "verify_sharded-00002-of-00002.safetensors: unexpected d",
+                "total_size 24 in index, tensors hold 16",
+            ]
+        );
+
+        // A truncated shard.
+        write_shard(&second, &["c"]);
+        let buffer = std::fs::read(&second).unwrap();
+        std::fs::write(&second, &buffer[..buffer.len() - 1]).unwrap();
+        let diffs = verify_sharded(index_path).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert!(diffs[0].contains("size"));
+
+        assert!(verify_sharded(dir.join("verify_missing.json").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn reshard_balanced() {
+        let tensor = |len: usize| CowTensor {
+            dtype: RDtype::U8,
+            shape: vec![len],
+            data: Cow::Owned(vec![len as u8; len]),
+        };
+        let tensors = vec![
+            ("a", tensor(8)),
+            ("b", tensor(5)),
+            ("c", tensor(4)),
+            ("d", tensor(3)),
+        ];
+        let info = HashMap::from([("format".to_string(), "pt".to_string())]);
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let in_path = dir.join("reshard_in.safetensors");
+        safetensors::tensor::serialize_to_file(tensors, Some(info), &in_path).unwrap();
+        let prefix = dir.join("reshard_out");
+        let prefix = prefix.to_str().unwrap();
+
+        let shards = reshard(in_path.to_str().unwrap(), 2, prefix).unwrap();
+        assert_eq!(
+            shards,
+            vec![
+                format!("{prefix}-00001-of-00002.safetensors"),
+                format!("{prefix}-00002-of-00002.safetensors"),
+            ]
+        );
+        let mut sizes = Vec::new();
+        for shard in &shards {
+            let buffer = std::fs::read(shard).unwrap();
+            let loaded = safetensors::SafeTensors::deserialize(&buffer).unwrap();
+            let (n, metadata) = safetensors::SafeTensors::read_metadata(&buffer).unwrap();
+            assert_eq!(metadata.metadata().as_ref().unwrap()["format"], "pt");
+            sizes.push(buffer.len() - N_LEN - n);
+            for (name, view) in loaded.tensors() {
+                assert!(
+                    view.data().iter().all(|&b| b as usize == view.data().len()),
+                    "{name}"
+                );
+            }
+        }
+        assert_eq!(sizes, vec![11, 9]);
+
+        // Resharding from the index just written, into more shards than tensors.
+        let index_path = format!("{prefix}.safetensors.index.json");
+        assert!(verify_sharded(&index_path).unwrap().is_empty());
+        let prefix = dir.join("reshard_again");
+        let prefix = prefix.to_str().unwrap();
+        let shards = reshard(&index_path, 5, prefix).unwrap();
+        assert_eq!(shards.len(), 5);
+        let last = std::fs::read(&shards[4]).unwrap();
+        assert_eq!(
+            safetensors::SafeTensors::deserialize(&last).unwrap().len(),
+            0
+        );
+        assert!(verify_sharded(&format!("{prefix}.safetensors.index.json"))
+            .unwrap()
+            .is_empty());
+
+        assert!(reshard(&index_path, 0, prefix).is_err());
+
+        // In place: every output shard is also a source shard.
+        let prefix = dir.join("reshard_out");
+        let prefix = prefix.to_str().unwrap();
+        let index_path = format!("{prefix}.safetensors.index.json");
+        let before = std::fs::read(format!("{prefix}-00001-of-00002.safetensors")).unwrap();
+        let shards = reshard(&index_path, 2, prefix).unwrap();
+        assert_eq!(std::fs::read(&shards[0]).unwrap(), before);
+        assert!(verify_sharded(&index_path).unwrap().is_empty());
+        let sizes: Vec<_> = shards
+            .iter()
+            .map(|shard| {
+                let buffer = std::fs::read(shard).unwrap();
+                safetensors::SafeTensors::deserialize(&buffer)
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 4);
+        let leftovers = std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().unwrap() == "tmp")
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+}
@@ -33,6 +33,28 @@ pub fn bench_serialize(c: &mut Criterion) {
     });
 }
 
+// `n_tensors` 2x2 F32 tensors all viewing `data`, the shape of MoE models
+// with tens of thousands of tiny tensors.
+fn get_many_tensors(data: &[u8], n_tensors: usize) -> HashMap<String, TensorView<'_>> {
+    (0..n_tensors)
+        .map(|i| {
+            let tensor = TensorView::new(Dtype::F32, vec![2, 2], data).unwrap();
+            (format!("model.layers.{i}.weight"), tensor)
+        })
+        .collect()
+}
+
+pub fn bench_serialize_50k(c: &mut Criterion) {
+    let data = [0u8; 16];
+    let metadata = get_many_tensors(&data, 50_000);
+
+    c.bench_function("Serialize 50k tensors", |b| {
+        b.iter(|| {
+            let _serialized = serialize(black_box(&metadata), black_box(None));
+        })
+    });
+}
+
 pub fn bench_deserialize(c: &mut Criterion) {
     let (data, shape, dtype) = get_sample_data();
     let n_layers = 5;
@@ -53,6 +75,6 @@ pub fn bench_deserialize(c: &mut Criterion) {
     });
 }
 
-criterion_group!(bench_ser, bench_serialize);
+criterion_group!(bench_ser, bench_serialize, bench_serialize_50k);
 criterion_group!(bench_de, bench_deserialize);
 criterion_main!(bench_ser, bench_de);
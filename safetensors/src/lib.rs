@@ -8,6 +8,9 @@ pub mod tensor;
 pub use tensor::serialize_to_file;
 pub use tensor::{serialize, Dtype, SafeTensorError, SafeTensors, View};
 
+/// Version of this crate, as declared in its manifest.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[cfg(feature = "alloc")]
 #[macro_use]
 extern crate alloc;